@@ -0,0 +1,689 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::processor::RAM_SIZE;
+use crate::symbols::Symbols;
+
+/// Programs are assembled to run from the usual CHIP-8 load address.
+pub const ORIGIN: usize = 0x200;
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Which instruction set extensions the assembler accepts.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Target {
+    /// The original instruction set.
+    Chip8,
+    /// CHIP-8 plus the SUPER-CHIP 1.1 instructions.
+    SuperChip,
+    /// The XO-CHIP instructions, including the SUPER-CHIP ones.
+    XoChip
+}
+
+impl Target {
+    /// Maps the platform names used by `--platform` to the instructions they support.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "vip" | "chip48" => Some(Target::Chip8),
+            "schip" => Some(Target::SuperChip),
+            "xochip" => Some(Target::XoChip),
+            _ => None,
+        }
+    }
+}
+
+/// An assembly error, with the position in the source it was found at.
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    /// The source file, as given or as named by the `include` that read it.
+    pub file: String,
+    /// The line, counting from 1.
+    pub line: usize,
+    /// The column, counting from 1.
+    pub column: usize,
+    /// What went wrong.
+    pub message: String
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// A ROM ready to be loaded at [`ORIGIN`], along with the addresses of its labels.
+pub struct Assembly {
+    /// The assembled bytes.
+    pub rom: Vec<u8>,
+    /// Every label and the address it points at.
+    pub symbols: Symbols
+}
+
+/// Assembles a source file, resolving includes relative to the file's directory.
+pub fn assemble_file(path: &Path, target: Target) -> Result<Assembly, AsmError> {
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: e.to_string()
+    })?;
+
+    let mut assembler = Assembler::new(target);
+    assembler.process(&source, &path.display().to_string(), path.parent().unwrap_or(Path::new(".")), 0)?;
+    assembler.finish()
+}
+
+#[derive(Clone, Debug)]
+struct Position {
+    file: String,
+    line: usize,
+    column: usize
+}
+
+impl Position {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.line, column: self.column, message: message.into() }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Number(i64),
+    Str(String),
+    Symbol(char)
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    position: Position
+}
+
+impl Token {
+    fn is_word(&self, word: &str) -> bool {
+        matches!(&self.kind, TokenKind::Word(w) if w.eq_ignore_ascii_case(word))
+    }
+
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.kind == TokenKind::Symbol(symbol)
+    }
+}
+
+enum StatementKind {
+    Instruction { mnemonic: String, operands: Vec<Vec<Token>> },
+    Data { operands: Vec<Vec<Token>>, width: usize } // db (1 byte) or dw (2 bytes)
+}
+
+struct Statement {
+    address: usize,
+    position: Position,
+    kind: StatementKind
+}
+
+// An instruction operand after its tokens have been classified
+enum Operand {
+    V(u16),
+    I,
+    IndirectI, // [I]
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(i64),
+    Value(i64)
+}
+
+struct Assembler {
+    target: Target,
+    address: usize,
+    statements: Vec<Statement>,
+    labels: HashMap<String, i64>,
+    symbols: Symbols
+}
+
+impl Assembler {
+    fn new(target: Target) -> Self {
+        Assembler {
+            target,
+            address: ORIGIN,
+            statements: Vec::new(),
+            labels: HashMap::new(),
+            symbols: Symbols::default()
+        }
+    }
+
+    // First pass: reads the source, assigns an address to every statement and collects labels
+    fn process(&mut self, source: &str, file: &str, dir: &Path, depth: usize) -> Result<(), AsmError> {
+        for (index, line) in source.lines().enumerate() {
+            let tokens = tokenize(line, file, index + 1)?;
+            let mut tokens = tokens.as_slice();
+
+            // label:
+            if let [Token { kind: TokenKind::Word(name), position }, colon, rest @ ..] = tokens
+                && colon.is_symbol(':')
+            {
+                self.define(name, self.address as i64, position)?;
+                self.symbols.insert(self.address, name.clone());
+                tokens = rest;
+            }
+
+            let [first, rest @ ..] = tokens else {
+                continue;
+            };
+            let TokenKind::Word(word) = &first.kind else {
+                return Err(first.position.error("expected an instruction, directive or label"));
+            };
+
+            // NAME equ value
+            if let [keyword, value @ ..] = rest
+                && (keyword.is_word("equ") || keyword.is_symbol('='))
+            {
+                let value = self.evaluate(value, &keyword.position)?;
+                self.define(word, value, &first.position)?;
+                continue;
+            }
+
+            let operands = split_operands(rest, &first.position)?;
+            let size = match word.to_ascii_lowercase().as_str() {
+                "org" => {
+                    let [operand] = operands.as_slice() else {
+                        return Err(first.position.error("org takes one address"));
+                    };
+                    let address = self.evaluate(operand, &first.position)?;
+                    if address < self.address as i64 {
+                        return Err(first.position.error(format!("org can't move backwards to {:#05x}", address)));
+                    }
+                    self.address = address as usize;
+                    continue;
+                },
+                "include" => {
+                    let [operand] = operands.as_slice() else {
+                        return Err(first.position.error("include takes one file name"));
+                    };
+                    let [Token { kind: TokenKind::Str(name), position }] = operand.as_slice() else {
+                        return Err(first.position.error("include takes a quoted file name"));
+                    };
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(position.error("includes are nested too deeply"));
+                    }
+
+                    let path: PathBuf = dir.join(name);
+                    let source = fs::read_to_string(&path)
+                        .map_err(|e| position.error(format!("could not read {}: {}", path.display(), e)))?;
+                    self.process(&source, &path.display().to_string(), path.parent().unwrap_or(dir), depth + 1)?;
+                    continue;
+                },
+                "db" => {
+                    let size = operands.iter().map(|operand| match operand.as_slice() {
+                        [Token { kind: TokenKind::Str(text), .. }] => text.len(),
+                        _ => 1,
+                    }).sum();
+                    self.push(first, StatementKind::Data { operands, width: 1 }, size)?;
+                    continue;
+                },
+                "dw" => {
+                    let size = operands.len() * 2;
+                    self.push(first, StatementKind::Data { operands, width: 2 }, size)?;
+                    continue;
+                },
+                // Long I loads are followed by a 16-bit address
+                "ld" if operands.get(1).and_then(|operand| operand.first()).is_some_and(|token| token.is_word("long")) => 4,
+                _ => 2,
+            };
+
+            self.push(first, StatementKind::Instruction { mnemonic: word.to_ascii_uppercase(), operands }, size)?;
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, first: &Token, kind: StatementKind, size: usize) -> Result<(), AsmError> {
+        if self.address + size > RAM_SIZE {
+            return Err(first.position.error("program doesn't fit in memory"));
+        }
+
+        self.statements.push(Statement { address: self.address, position: first.position.clone(), kind });
+        self.address += size;
+
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: i64, position: &Position) -> Result<(), AsmError> {
+        if self.labels.insert(name.to_string(), value).is_some() {
+            return Err(position.error(format!("`{}` is already defined", name)));
+        }
+        Ok(())
+    }
+
+    // Second pass: encodes every statement now that all labels are known
+    fn finish(self) -> Result<Assembly, AsmError> {
+        let mut rom = vec![0u8; self.address - ORIGIN];
+
+        for statement in &self.statements {
+            let bytes = match &statement.kind {
+                StatementKind::Instruction { mnemonic, operands } => self.encode(mnemonic, operands, &statement.position)?,
+                StatementKind::Data { operands, width } => self.encode_data(operands, *width, &statement.position)?,
+            };
+
+            let offset = statement.address - ORIGIN;
+            rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        }
+
+        Ok(Assembly { rom, symbols: self.symbols })
+    }
+
+    fn encode_data(&self, operands: &[Vec<Token>], width: usize, position: &Position) -> Result<Vec<u8>, AsmError> {
+        let mut bytes = Vec::new();
+
+        for operand in operands {
+            if let [Token { kind: TokenKind::Str(text), .. }] = operand.as_slice()
+                && width == 1
+            {
+                bytes.extend_from_slice(text.as_bytes());
+                continue;
+            }
+
+            let value = self.evaluate(operand, position)?;
+            if width == 1 {
+                bytes.push(check_byte(value, &operand[0].position)?);
+            } else {
+                if !(0..=0xFFFF).contains(&value) {
+                    return Err(operand[0].position.error(format!("{} doesn't fit in a word", value)));
+                }
+                bytes.extend_from_slice(&(value as u16).to_be_bytes());
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    fn encode(&self, mnemonic: &str, operands: &[Vec<Token>], position: &Position) -> Result<Vec<u8>, AsmError> {
+        let classified = operands.iter()
+            .map(|operand| self.classify(operand, position))
+            .collect::<Result<Vec<_>, _>>()?;
+        let at = |index: usize| &operands[index][0].position;
+
+        let addr = |value: i64, index: usize| -> Result<u16, AsmError> {
+            if !(0..=0xFFF).contains(&value) {
+                return Err(at(index).error(format!("address {:#x} is out of range (0x000-0xfff)", value)));
+            }
+            Ok(value as u16)
+        };
+        let byte = |value: i64, index: usize| check_byte(value, at(index)).map(|byte| byte as u16);
+        let nibble = |value: i64, index: usize, max: i64| -> Result<u16, AsmError> {
+            if !(0..=max).contains(&value) {
+                return Err(at(index).error(format!("{} is out of range (0-{})", value, max)));
+            }
+            Ok(value as u16)
+        };
+        let require = |target: Target| -> Result<(), AsmError> {
+            if self.target < target {
+                let name = if target == Target::SuperChip { "SUPER-CHIP (--platform schip)" } else { "XO-CHIP (--platform xochip)" };
+                return Err(position.error(format!("{} needs {}", mnemonic, name)));
+            }
+            Ok(())
+        };
+
+        use Operand::*;
+        let opcode: u16 = match (mnemonic, classified.as_slice()) {
+            ("NOP", []) => 0x0000,
+            ("CLS", []) => 0x00E0,
+            ("RET", []) => 0x00EE,
+            ("SCD", [Value(n)]) => { require(Target::SuperChip)?; 0x00C0 | nibble(*n, 0, 15)? },
+            ("SCR", []) => { require(Target::SuperChip)?; 0x00FB },
+            ("SCL", []) => { require(Target::SuperChip)?; 0x00FC },
+            ("EXIT", []) => { require(Target::SuperChip)?; 0x00FD },
+            ("LOW", []) => { require(Target::SuperChip)?; 0x00FE },
+            ("HIGH", []) => { require(Target::SuperChip)?; 0x00FF },
+            ("JP", [Value(nnn)]) => 0x1000 | addr(*nnn, 0)?,
+            ("JP", [V(0), Value(nnn)]) => 0xB000 | addr(*nnn, 1)?,
+            ("JP", [V(x), Value(nnn)]) => { // BXNN, on platforms that jump to XNN + Vx
+                let nnn = addr(*nnn, 1)?;
+                if nnn >> 8 != *x {
+                    return Err(at(1).error(format!("JP V{:X} needs an address starting with {:X}, got {:#05x}", x, x, nnn)));
+                }
+                0xB000 | nnn
+            },
+            ("CALL", [Value(nnn)]) => 0x2000 | addr(*nnn, 0)?,
+            ("SE", [V(x), Value(nn)]) => 0x3000 | x << 8 | byte(*nn, 1)?,
+            ("SE", [V(x), V(y)]) => 0x5000 | x << 8 | y << 4,
+            ("SNE", [V(x), Value(nn)]) => 0x4000 | x << 8 | byte(*nn, 1)?,
+            ("SNE", [V(x), V(y)]) => 0x9000 | x << 8 | y << 4,
+            ("SAVE", [V(x), V(y)]) => { require(Target::XoChip)?; 0x5002 | x << 8 | y << 4 },
+            ("LOAD", [V(x), V(y)]) => { require(Target::XoChip)?; 0x5003 | x << 8 | y << 4 },
+            ("LD", [V(x), Value(nn)]) => 0x6000 | x << 8 | byte(*nn, 1)?,
+            ("LD", [V(x), V(y)]) => 0x8000 | x << 8 | y << 4,
+            ("LD", [I, Value(nnn)]) => 0xA000 | addr(*nnn, 1)?,
+            ("LD", [I, Long(nnnn)]) => {
+                require(Target::XoChip)?;
+                if !(0..=0xFFFF).contains(nnnn) {
+                    return Err(at(1).error(format!("address {:#x} is out of range (0x0000-0xffff)", nnnn)));
+                }
+                return Ok(vec![0xF0, 0x00, (*nnnn >> 8) as u8, *nnnn as u8]);
+            },
+            ("LD", [V(x), Dt]) => 0xF007 | x << 8,
+            ("LD", [V(x), K]) => 0xF00A | x << 8,
+            ("LD", [Dt, V(x)]) => 0xF015 | x << 8,
+            ("LD", [St, V(x)]) => 0xF018 | x << 8,
+            ("LD", [F, V(x)]) => 0xF029 | x << 8,
+            ("LD", [Hf, V(x)]) => { require(Target::SuperChip)?; 0xF030 | x << 8 },
+            ("LD", [B, V(x)]) => 0xF033 | x << 8,
+            ("LD", [IndirectI, V(x)]) => 0xF055 | x << 8,
+            ("LD", [V(x), IndirectI]) => 0xF065 | x << 8,
+            ("LD", [R, V(x)]) => { require(Target::SuperChip)?; 0xF075 | x << 8 },
+            ("LD", [V(x), R]) => { require(Target::SuperChip)?; 0xF085 | x << 8 },
+            ("ADD", [V(x), Value(nn)]) => 0x7000 | x << 8 | byte(*nn, 1)?,
+            ("ADD", [V(x), V(y)]) => 0x8004 | x << 8 | y << 4,
+            ("ADD", [I, V(x)]) => 0xF01E | x << 8,
+            ("OR", [V(x), V(y)]) => 0x8001 | x << 8 | y << 4,
+            ("AND", [V(x), V(y)]) => 0x8002 | x << 8 | y << 4,
+            ("XOR", [V(x), V(y)]) => 0x8003 | x << 8 | y << 4,
+            ("SUB", [V(x), V(y)]) => 0x8005 | x << 8 | y << 4,
+            ("SHR", [V(x)]) => 0x8006 | x << 8 | x << 4,
+            ("SHR", [V(x), V(y)]) => 0x8006 | x << 8 | y << 4,
+            ("SUBN", [V(x), V(y)]) => 0x8007 | x << 8 | y << 4,
+            ("SHL", [V(x)]) => 0x800E | x << 8 | x << 4,
+            ("SHL", [V(x), V(y)]) => 0x800E | x << 8 | y << 4,
+            ("RND", [V(x), Value(nn)]) => 0xC000 | x << 8 | byte(*nn, 1)?,
+            ("DRW", [V(x), V(y), Value(n)]) => 0xD000 | x << 8 | y << 4 | nibble(*n, 2, 15)?,
+            ("SKP", [V(x)]) => 0xE09E | x << 8,
+            ("SKNP", [V(x)]) => 0xE0A1 | x << 8,
+            ("PLANE", [Value(n)]) => { require(Target::XoChip)?; 0xF001 | nibble(*n, 0, 3)? << 8 },
+            ("AUDIO", []) => { require(Target::XoChip)?; 0xF002 },
+            ("PITCH", [V(x)]) => { require(Target::XoChip)?; 0xF03A | x << 8 },
+            (_, _) => return Err(position.error(format!("invalid instruction or operands for {}", mnemonic))),
+        };
+
+        Ok(opcode.to_be_bytes().to_vec())
+    }
+
+    fn classify(&self, tokens: &[Token], position: &Position) -> Result<Operand, AsmError> {
+        if let [token] = tokens
+            && let TokenKind::Word(word) = &token.kind
+        {
+            let lower = word.to_ascii_lowercase();
+            if let Some(register) = lower.strip_prefix('v')
+                && register.len() == 1
+                && let Ok(x) = u16::from_str_radix(register, 16)
+            {
+                return Ok(Operand::V(x));
+            }
+
+            match lower.as_str() {
+                "i" => return Ok(Operand::I),
+                "dt" => return Ok(Operand::Dt),
+                "st" => return Ok(Operand::St),
+                "k" => return Ok(Operand::K),
+                "f" => return Ok(Operand::F),
+                "hf" => return Ok(Operand::Hf),
+                "b" => return Ok(Operand::B),
+                "r" => return Ok(Operand::R),
+                _ => {},
+            }
+        }
+
+        if let [open, i, close] = tokens
+            && open.is_symbol('[') && i.is_word("i") && close.is_symbol(']')
+        {
+            return Ok(Operand::IndirectI);
+        }
+
+        if let [long, rest @ ..] = tokens
+            && long.is_word("long")
+        {
+            return Ok(Operand::Long(self.evaluate(rest, &long.position)?));
+        }
+
+        Ok(Operand::Value(self.evaluate(tokens, position)?))
+    }
+
+    // Evaluates `term (+|- term)*`, where a term is a number or a label, optionally negated
+    fn evaluate(&self, tokens: &[Token], position: &Position) -> Result<i64, AsmError> {
+        let mut tokens = tokens.iter().peekable();
+        let mut total = 0i64;
+        let mut sign = 1i64;
+
+        loop {
+            let mut token = tokens.next().ok_or(position.error("expected a value"))?;
+            if token.is_symbol('-') {
+                sign = -sign;
+                token = tokens.next().ok_or(token.position.error("expected a value after `-`"))?;
+            }
+
+            let value = match &token.kind {
+                TokenKind::Number(value) => *value,
+                TokenKind::Word(name) => *self.labels.get(name)
+                    .ok_or(token.position.error(format!("undefined label or constant `{}`", name)))?,
+                _ => return Err(token.position.error("expected a number, label or constant")),
+            };
+            total += sign * value;
+
+            match tokens.next() {
+                None => return Ok(total),
+                Some(token) if token.is_symbol('+') => sign = 1,
+                Some(token) if token.is_symbol('-') => sign = -1,
+                Some(token) => return Err(token.position.error("expected `+`, `-` or the end of the value")),
+            }
+        }
+    }
+}
+
+fn check_byte(value: i64, position: &Position) -> Result<u8, AsmError> {
+    if !(-128..=255).contains(&value) {
+        return Err(position.error(format!("{} doesn't fit in a byte", value)));
+    }
+    Ok(value as u8)
+}
+
+// Splits the tokens after a mnemonic into comma-separated operands
+fn split_operands(tokens: &[Token], position: &Position) -> Result<Vec<Vec<Token>>, AsmError> {
+    if tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut operands = vec![Vec::new()];
+    for token in tokens {
+        if token.is_symbol(',') {
+            operands.push(Vec::new());
+        } else if let Some(operand) = operands.last_mut() {
+            operand.push(token.clone());
+        }
+    }
+
+    if operands.iter().any(Vec::is_empty) {
+        return Err(position.error("empty operand"));
+    }
+
+    Ok(operands)
+}
+
+fn tokenize(line: &str, file: &str, line_number: usize) -> Result<Vec<Token>, AsmError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+        let position = Position { file: file.to_string(), line: line_number, column: index + 1 };
+
+        if c.is_whitespace() {
+            index += 1;
+        } else if c == ';' {
+            break; // Comment
+        } else if c == '"' {
+            let end = chars[index + 1..].iter().position(|c| *c == '"')
+                .ok_or(position.error("unterminated string"))?;
+            let text: String = chars[index + 1..index + 1 + end].iter().collect();
+            tokens.push(Token { kind: TokenKind::Str(text), position });
+            index += end + 2;
+        } else if ",:[]+-=".contains(c) {
+            tokens.push(Token { kind: TokenKind::Symbol(c), position });
+            index += 1;
+        } else if c.is_alphanumeric() || "_.#$".contains(c) {
+            let length = chars[index..].iter()
+                .take_while(|c| c.is_alphanumeric() || "_.#$".contains(**c))
+                .count();
+            let word: String = chars[index..index + length].iter().collect();
+
+            let kind = if c.is_ascii_digit() || c == '#' || c == '$' {
+                TokenKind::Number(parse_number(&word).ok_or(position.error(format!("invalid number `{}`", word)))?)
+            } else {
+                TokenKind::Word(word)
+            };
+            tokens.push(Token { kind, position });
+            index += length;
+        } else {
+            return Err(position.error(format!("unexpected character `{}`", c)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Numbers are decimal, hex with a 0x, # or $ prefix, or binary with a 0b prefix
+fn parse_number(word: &str) -> Option<i64> {
+    let lower = word.to_ascii_lowercase();
+
+    if let Some(hex) = lower.strip_prefix("0x").or(lower.strip_prefix('#')).or(lower.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::disasm;
+    use crate::quirks::Quirks;
+
+    fn assemble(source: &str, target: Target) -> Result<Assembly, AsmError> {
+        let mut assembler = Assembler::new(target);
+        assembler.process(source, "test.s", Path::new("."), 0)?;
+        assembler.finish()
+    }
+
+    fn rom(source: &str) -> Vec<u8> {
+        assemble(source, Target::XoChip).unwrap().rom
+    }
+
+    fn error(source: &str, target: Target) -> AsmError {
+        assemble(source, target).err().unwrap()
+    }
+
+    #[test]
+    fn errors_point_at_the_line_and_column() {
+        let range = error("cls\n  ld v0, 300", Target::Chip8);
+        assert_eq!((range.line, range.column), (2, 10));
+        assert_eq!(range.to_string(), "test.s:2:10: 300 doesn't fit in a byte");
+
+        let undefined = error("jp nowhere", Target::Chip8);
+        assert_eq!((undefined.line, undefined.column), (1, 4));
+        assert_eq!(undefined.message, "undefined label or constant `nowhere`");
+    }
+
+    #[test]
+    fn labels_and_constants() {
+        let source = "
+            SPEED equ 3
+            TOP = #10
+            start:
+                ld v0, SPEED + 1
+                ld v1, TOP - SPEED
+                jp end
+            end: jp start
+        ";
+        let assembly = assemble(source, Target::Chip8).unwrap();
+
+        assert_eq!(assembly.rom, [0x60, 0x04, 0x61, 0x0D, 0x12, 0x06, 0x12, 0x00]);
+        assert_eq!(assembly.symbols.address("end"), Some(0x206));
+        assert_eq!(error("a: cls\na: cls", Target::Chip8).message, "`a` is already defined");
+    }
+
+    #[test]
+    fn data_strings_and_ranges() {
+        assert_eq!(rom("db \"Hi\", 1, -1\ndw $1234, 5"), [b'H', b'i', 1, 0xFF, 0x12, 0x34, 0x00, 0x05]);
+
+        assert_eq!(error("db 256", Target::Chip8).message, "256 doesn't fit in a byte");
+        assert_eq!(error("db -129", Target::Chip8).message, "-129 doesn't fit in a byte");
+        assert_eq!(error("dw 65536", Target::Chip8).message, "65536 doesn't fit in a word");
+        assert_eq!(error("db \"open", Target::Chip8).message, "unterminated string");
+    }
+
+    #[test]
+    fn long_loads_take_four_bytes() {
+        let assembly = assemble("ld i, long data\nld i, long $ABCD\ndata: db 1", Target::XoChip).unwrap();
+
+        assert_eq!(assembly.rom, [0xF0, 0x00, 0x02, 0x08, 0xF0, 0x00, 0xAB, 0xCD, 0x01]);
+        assert_eq!(assembly.symbols.address("data"), Some(0x208));
+    }
+
+    #[test]
+    fn extensions_need_their_target() {
+        assert_eq!(error("scr", Target::Chip8).message, "SCR needs SUPER-CHIP (--platform schip)");
+        assert_eq!(error("plane 1", Target::SuperChip).message, "PLANE needs XO-CHIP (--platform xochip)");
+        assert_eq!(error("ld i, long 0", Target::Chip8).message, "LD needs XO-CHIP (--platform xochip)");
+
+        assert!(assemble("scr\nld hf, v0", Target::SuperChip).is_ok());
+        assert!(assemble("scr\nplane 3\naudio", Target::XoChip).is_ok());
+    }
+
+    #[test]
+    fn includes_resolve_relative_to_the_including_file() {
+        let dir = env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.s"), "include \"lib/sprites.s\"\njp start").unwrap();
+        fs::write(dir.join("lib/sprites.s"), "include \"data.s\"\nstart: cls").unwrap();
+        fs::write(dir.join("lib/data.s"), "VALUE equ 7").unwrap();
+        fs::write(dir.join("loop.s"), "include \"loop.s\"").unwrap();
+
+        let assembly = assemble_file(&dir.join("main.s"), Target::Chip8);
+        let self_include = assemble_file(&dir.join("loop.s"), Target::Chip8);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(assembly.unwrap().rom, [0x00, 0xE0, 0x12, 0x00]);
+        assert_eq!(self_include.err().unwrap().message, "includes are nested too deeply");
+    }
+
+    // Every instruction the disassembler prints assembles back to the same bytes
+    #[test]
+    fn disassembly_round_trips() {
+        let opcodes: &[&[u8]] = &[
+            &[0x00, 0xE0], &[0x00, 0xEE], &[0x00, 0xC5], &[0x00, 0xFB], &[0x00, 0xFC], &[0x00, 0xFD],
+            &[0x00, 0xFE], &[0x00, 0xFF], &[0x1A, 0xBC], &[0x2A, 0xBC], &[0x31, 0x23], &[0x41, 0x23],
+            &[0x51, 0x20], &[0x51, 0x22], &[0x51, 0x23], &[0x6A, 0xFF], &[0x7A, 0x01], &[0x8A, 0xB0],
+            &[0x8A, 0xB1], &[0x8A, 0xB2], &[0x8A, 0xB3], &[0x8A, 0xB4], &[0x8A, 0xB5], &[0x8A, 0xB6],
+            &[0x8A, 0xB7], &[0x8A, 0xBE], &[0x9A, 0xB0], &[0xA1, 0x23], &[0xB1, 0x23], &[0xC1, 0x0F],
+            &[0xD1, 0x2F], &[0xE1, 0x9E], &[0xE1, 0xA1], &[0xF0, 0x00, 0x12, 0x34], &[0xF2, 0x01],
+            &[0xF0, 0x02], &[0xF1, 0x07], &[0xF1, 0x0A], &[0xF1, 0x15], &[0xF1, 0x18], &[0xF1, 0x1E],
+            &[0xF1, 0x29], &[0xF1, 0x30], &[0xF1, 0x33], &[0xF1, 0x3A], &[0xF1, 0x55], &[0xF1, 0x65],
+            &[0xF1, 0x75], &[0xF1, 0x85]
+        ];
+
+        for bytes in opcodes {
+            let (mnemonic, length) = disasm::decode(bytes, &Quirks::COSMAC_VIP).unwrap();
+            assert_eq!(length, bytes.len(), "{}", mnemonic);
+            assert_eq!(rom(&mnemonic), *bytes, "{}", mnemonic);
+        }
+
+        // BXNN reads as a jump on Vx on the platforms that have it
+        let (mnemonic, _) = disasm::decode(&[0xB1, 0x23], &Quirks::SCHIP).unwrap();
+        assert_eq!(mnemonic, "JP V1, 0x123");
+        assert_eq!(rom(&mnemonic), [0xB1, 0x23]);
+        let mismatch = error("JP V2, 0x123", Target::Chip8);
+        assert_eq!((mismatch.column, mismatch.message.as_str()), (8, "JP V2 needs an address starting with 2, got 0x123"));
+    }
+}
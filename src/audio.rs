@@ -0,0 +1,391 @@
+use std::{
+    f32::consts::PI,
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+};
+
+/// The shape of the tone played while the sound timer is active.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    /// Half the period high and half low, like the original beeper.
+    Square,
+    /// A pure tone.
+    Sine,
+    /// Rises and falls linearly.
+    Triangle,
+    /// Rises linearly and drops at the end of each period.
+    Sawtooth
+}
+
+impl Waveform {
+    /// Parses the names used in config files: `square`, `sine`, `triangle` or `sawtooth`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            _ => None,
+        }
+    }
+}
+
+/// How the beeper sounds when the program hasn't loaded an XO-CHIP audio pattern.
+#[derive(Clone, Copy, Debug)]
+pub struct ToneSettings {
+    /// The tone's pitch in Hz.
+    pub frequency: f32,
+    /// From 0.0 (silent) to 1.0.
+    pub volume: f32,
+    /// The shape of the tone.
+    pub waveform: Waveform
+}
+
+impl Default for ToneSettings {
+    fn default() -> Self {
+        ToneSettings {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square
+        }
+    }
+}
+
+/// An XO-CHIP audio pattern: 128 1-bit samples, looped at a rate set by the pitch register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioPattern {
+    /// The samples, most significant bit first.
+    pub bits: [u8; 16],
+    /// The pitch register, set by FX3A.
+    pub pitch: u8
+}
+
+impl AudioPattern {
+    /// Playback rate in bits per second. A pitch of 64 plays at 4000Hz.
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    fn bit(&self, position: usize) -> bool {
+        self.bits[(position / 8) % 16] & (0b1000_0000 >> (position % 8)) != 0
+    }
+}
+
+/// Generates the beeper tone one sample at a time.
+pub struct Oscillator {
+    settings: ToneSettings,
+    sample_rate: u32,
+    phase: f32, // 0.0 - 1.0, position within the current period
+    pattern: Option<AudioPattern>, // Replaces the waveform once the program loads one
+    pattern_position: f32 // 0.0 - 128.0, position within the pattern in bits
+}
+
+impl Oscillator {
+    /// Creates an oscillator that produces `sample_rate` samples per second.
+    pub fn new(settings: ToneSettings, sample_rate: u32) -> Self {
+        Oscillator { settings, sample_rate, phase: 0.0, pattern: None, pattern_position: 0.0 }
+    }
+
+    /// Plays an audio pattern instead of the waveform, or goes back to the waveform with `None`.
+    pub fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.pattern = pattern;
+    }
+
+    /// The next sample, between `-volume` and `volume`.
+    pub fn next_sample(&mut self) -> f32 {
+        if let Some(pattern) = self.pattern {
+            let value = if pattern.bit(self.pattern_position as usize) { 1.0 } else { -1.0 };
+            self.pattern_position = (self.pattern_position + pattern.playback_rate() / self.sample_rate as f32) % 128.0;
+
+            return value * self.settings.volume;
+        }
+
+        let value = match self.settings.waveform {
+            Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (self.phase * 2.0 * PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+        };
+
+        self.phase = (self.phase + self.settings.frequency / self.sample_rate as f32).fract();
+
+        value * self.settings.volume
+    }
+}
+
+/// Something that can play the beeper.
+///
+/// `tick` is called once per 60Hz timer tick, so the tone always starts and stops on a timer
+/// boundary.
+pub trait AudioBackend {
+    /// Plays a tick: the tone (or `pattern`, if the program loaded one) if `active`, silence
+    /// otherwise.
+    fn tick(&mut self, active: bool, pattern: Option<AudioPattern>);
+}
+
+/// Drives an [`AudioBackend`] from the processor's sound timer.
+pub struct Beeper {
+    backend: Box<dyn AudioBackend>
+}
+
+impl Beeper {
+    /// Creates a beeper that plays through `backend`.
+    pub fn new(backend: Box<dyn AudioBackend>) -> Self {
+        Beeper { backend }
+    }
+
+    /// Should be called on every 60Hz timer boundary, before the timers are decremented.
+    pub fn update(&mut self, sound_timer: u8, pattern: Option<AudioPattern>) {
+        self.backend.tick(sound_timer > 0, pattern);
+    }
+}
+
+/// Plays nothing. Used when the emulator is muted or there is no audio device.
+#[derive(Default)]
+pub struct NullBackend {
+    /// Whether the tone would be playing.
+    pub active: bool,
+    /// How many ticks have been played.
+    pub ticks: u64
+}
+
+impl AudioBackend for NullBackend {
+    fn tick(&mut self, active: bool, _pattern: Option<AudioPattern>) {
+        self.active = active;
+        self.ticks += 1;
+    }
+}
+
+/// Renders the beeper into a 16-bit mono PCM WAV file, one 60th of a second per tick.
+pub struct WavBackend {
+    writer: BufWriter<File>,
+    oscillator: Oscillator,
+    samples_per_tick: u32,
+    samples_written: u32
+}
+
+impl WavBackend {
+    /// Samples per second in the WAV file.
+    pub const SAMPLE_RATE: u32 = 44100;
+
+    /// Creates the WAV file, replacing it if it exists.
+    pub fn create(path: &String, settings: ToneSettings) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_wav_header(&mut writer, Self::SAMPLE_RATE, 0)?; // Sizes are patched in by finish()
+
+        Ok(WavBackend {
+            writer,
+            oscillator: Oscillator::new(settings, Self::SAMPLE_RATE),
+            samples_per_tick: Self::SAMPLE_RATE / 60,
+            samples_written: 0
+        })
+    }
+
+    /// Writes the final chunk sizes into the header. Also called when the backend is dropped.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.writer, Self::SAMPLE_RATE, self.samples_written)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+
+    fn write_tick(&mut self, active: bool) -> io::Result<()> {
+        for _ in 0..self.samples_per_tick {
+            let sample = if active { self.oscillator.next_sample() } else { 0.0 };
+            self.writer.write_all(&((sample * i16::MAX as f32) as i16).to_le_bytes())?;
+        }
+        self.samples_written += self.samples_per_tick;
+
+        Ok(())
+    }
+}
+
+impl AudioBackend for WavBackend {
+    fn tick(&mut self, active: bool, pattern: Option<AudioPattern>) {
+        self.oscillator.set_pattern(pattern);
+        if let Err(err) = self.write_tick(active) {
+            eprintln!("[-] Could not write audio: {}", err);
+        }
+    }
+}
+
+impl Drop for WavBackend {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+fn write_wav_header(writer: &mut impl Write, sample_rate: u32, samples: u32) -> io::Result<()> {
+    let data_size = samples * 2; // 16-bit mono
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?; // Chunk size
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // Channels
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?; // Byte rate
+    writer.write_all(&2u16.to_le_bytes())?; // Block align
+    writer.write_all(&16u16.to_le_bytes())?; // Bits per sample
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())
+}
+
+#[cfg(feature = "cpal")]
+pub use device::DeviceBackend;
+
+#[cfg(feature = "cpal")]
+mod device {
+    use std::sync::{Arc, Mutex};
+
+    use cpal::{
+        FromSample, SampleFormat, SizedSample, Stream, StreamConfig,
+        traits::{DeviceTrait, HostTrait, StreamTrait},
+    };
+
+    use super::{AudioBackend, AudioPattern, Oscillator, ToneSettings};
+
+    // What the audio thread should currently be playing
+    #[derive(Default)]
+    struct Playback {
+        active: bool,
+        pattern: Option<AudioPattern>
+    }
+
+    /// Plays the beeper on the default output device.
+    pub struct DeviceBackend {
+        playback: Arc<Mutex<Playback>>,
+        _stream: Stream // Playback stops when the stream is dropped
+    }
+
+    impl DeviceBackend {
+        pub fn open(settings: ToneSettings) -> Result<Self, String> {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or("no output device available")?;
+            let supported = device.default_output_config().map_err(|e| e.to_string())?;
+
+            let format = supported.sample_format();
+            let config: StreamConfig = supported.into();
+            let playback = Arc::new(Mutex::new(Playback::default()));
+
+            let stream = match format {
+                SampleFormat::F32 => build_stream::<f32>(&device, &config, settings, playback.clone()),
+                SampleFormat::I16 => build_stream::<i16>(&device, &config, settings, playback.clone()),
+                SampleFormat::U16 => build_stream::<u16>(&device, &config, settings, playback.clone()),
+                other => Err(format!("unsupported sample format {}", other)),
+            }?;
+            stream.play().map_err(|e| e.to_string())?;
+
+            Ok(DeviceBackend { playback, _stream: stream })
+        }
+    }
+
+    impl AudioBackend for DeviceBackend {
+        fn tick(&mut self, active: bool, pattern: Option<AudioPattern>) {
+            if let Ok(mut playback) = self.playback.lock() {
+                playback.active = active;
+                playback.pattern = pattern;
+            }
+        }
+    }
+
+    fn build_stream<T>(
+        device: &cpal::Device,
+        config: &StreamConfig,
+        settings: ToneSettings,
+        playback: Arc<Mutex<Playback>>
+    ) -> Result<Stream, String>
+    where
+        T: SizedSample + FromSample<f32>,
+    {
+        let channels = config.channels as usize;
+        let mut oscillator = Oscillator::new(settings, config.sample_rate.0);
+
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let playing = match playback.lock() {
+                    Ok(playback) => {
+                        oscillator.set_pattern(playback.pattern);
+                        playback.active
+                    },
+                    Err(_) => false,
+                };
+                for frame in data.chunks_mut(channels) {
+                    let sample = if playing { oscillator.next_sample() } else { 0.0 };
+                    frame.fill(T::from_sample(sample));
+                }
+            },
+            |err| eprintln!("[-] Audio stream error: {}", err),
+            None
+        ).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    // Renders the ticks to a WAV file and returns its bytes
+    fn render(name: &str, ticks: &[bool]) -> Vec<u8> {
+        let path = env::temp_dir().join(format!("chip8-{}-{}.wav", name, std::process::id()))
+            .to_string_lossy().into_owned();
+
+        let mut backend = WavBackend::create(&path, ToneSettings::default()).unwrap();
+        for active in ticks {
+            backend.tick(*active, None);
+        }
+        drop(backend);
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn wav_header_has_the_final_sizes() {
+        let bytes = render("sizes", &[false, true, true]);
+        let data_size = 3 * WavBackend::SAMPLE_RATE / 60 * 2;
+
+        assert_eq!(bytes.len(), 44 + data_size as usize);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4), 36 + data_size);
+        assert_eq!(u32_at(&bytes, 24), WavBackend::SAMPLE_RATE);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(&bytes, 40), data_size);
+    }
+
+    #[test]
+    fn tone_starts_and_stops_on_tick_boundaries() {
+        let bytes = render("ticks", &[false, true, false]);
+        let samples: Vec<i16> = bytes[44..].chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+        let ticks: Vec<&[i16]> = samples.chunks(WavBackend::SAMPLE_RATE as usize / 60).collect();
+
+        assert_eq!(ticks.len(), 3);
+        assert!(ticks[0].iter().all(|sample| *sample == 0));
+        assert!(ticks[1].iter().all(|sample| *sample != 0)); // A square wave never crosses zero
+        assert!(ticks[2].iter().all(|sample| *sample == 0));
+    }
+
+    #[test]
+    fn patterns_play_their_bits() {
+        let pattern = AudioPattern { bits: [0xFF; 16], pitch: 64 };
+        let mut oscillator = Oscillator::new(ToneSettings::default(), 8000);
+        oscillator.set_pattern(Some(pattern));
+
+        assert_eq!(pattern.playback_rate(), 4000.0);
+        assert!((0..100).all(|_| oscillator.next_sample() == ToneSettings::default().volume));
+    }
+}
@@ -0,0 +1,612 @@
+use std::{fs, io::{self, Read}, path::Path};
+
+use chip8::asm::Target;
+use chip8::audio::{ToneSettings, Waveform};
+use chip8::database::Database;
+use chip8::frame::{CpuSpeed, SPEED_RANGE};
+use chip8::palette::Palette;
+use chip8::processor::PROGRAM_START;
+use chip8::quirks::Quirks;
+use chip8::rng::RandomMode;
+
+use crate::config::{Config, Defaults, MAX_SCALE};
+use crate::headless::Until;
+
+pub const USAGE: &str = "Usage: Chip8-Emulator [run] <rom> [options]
+       Chip8-Emulator run <rom> --headless [headless options]
+       Chip8-Emulator headless <rom> [headless options]
+       Chip8-Emulator disasm <rom> [--recursive] [--platform <name>] [--origin <addr>] [--output <file>]
+       Chip8-Emulator asm <source> [--platform <name>] [--output <file>] [--symbols <file>]
+       Chip8-Emulator info <rom> [--database <file>]
+       Chip8-Emulator test <rom> --expect <file> [--bless] [headless options]
+       Chip8-Emulator --help | --version
+
+Commands:
+  run                   Run a rom in a window. - as the rom reads it from stdin. This is what
+                        happens when no command is given.
+  headless              Run a rom without a window, the same as run --headless
+  disasm                Turn a rom into assembly
+  asm                   Build a rom from assembly
+  info                  Show a rom's size, SHA-1 hash and the settings the rom database has for it
+  test                  Run a rom headless and check the screen it ends on
+
+Options:
+  --platform <name>     Quirks to emulate: vip, chip48, schip or xochip (default vip, or the
+                        platform the rom database recommends)
+  --origin <addr>       Hex address to load the rom at and start running from, e.g. 600 for
+                        ETI-660 programs (default 200)
+  --speed <hz>          Instructions per second, or vip for COSMAC VIP instruction timing
+                        (default 600, or the speed in the rom's .cfg file or the rom database)
+  --scale <n>           Window pixels per CHIP-8 pixel, 1-32 (default 10)
+  --fullscreen          Start in fullscreen
+  --colors <colors>     Four comma-separated RRGGBB colors for the XO-CHIP plane combinations
+                        (--palette works too)
+  --seed <n>            Seed for the random number generator, to make a run reproducible
+  --random <mode>       xorshift, or vip for the COSMAC VIP's own routine (default xorshift)
+  --mute                Disable sound
+  --headless            Run without a window, see the headless options below
+  --database <file>     Rom database with settings for known roms, on top of the bundled one
+  --config <file>       Config file to use instead of ~/.config/chip8/config.toml
+  --keymap <file>       Load a key mapping file
+  --debug               Start paused with a debugger console on stdin
+  --symbols <file>      Symbol file from the assembler, used by the debugger
+  --frequency <hz>      Beeper frequency (default 440)
+  --volume <0-1>        Beeper volume (default 0.25)
+  --waveform <shape>    square, sine, triangle or sawtooth (default square)
+  --wav <file>          Record the beeper to a WAV file instead of playing it
+  --record <file>       Record the keys pressed in each frame to a movie file
+  --replay <file>       Play a movie file back, using its seed and quirks
+  --rewind <seconds>    How much history to keep for rewinding, 0 disables it (default 10)
+  --rewind-speed <n>    Frames to step back per frame while rewinding (default 1)
+  --fast-forward <n>    How many times faster fast-forward runs, or uncapped (default 4)
+  --help, -h            Show this help
+  --version, -V         Show the version
+
+Headless options:
+  --frames <n>          Frames to run at most (default 600, ten seconds)
+  --until <condition>   Stop early on exit (00FD), idle (a jump to itself) or pc=<addr>
+  --keys <file>         Key script with `<frame> <hex key> down|up` lines
+  --screenshot <file>   Write the screen as a PNG (.png) or as text (anything else, - for stdout)
+  --registers <file>    Write the registers as JSON (- for stdout)
+  --platform, --origin, --speed, --colors, --seed, --random, --database and --config work as
+  above. Headless runs are silent and use seed 0 unless given another.
+
+Disassembler options:
+  --recursive           Follow jumps, calls and skips so data isn't decoded as code
+  --platform <name>     Quirks to decode with: on chip48 and schip, BNNN shows as JP VX, XNN
+                        (default vip)
+  --origin <addr>       Hex address the rom is loaded at (default 200)
+  --output <file>       Write the assembly to a file instead of stdout
+
+Assembler options:
+  --platform <name>     Allow SUPER-CHIP (schip) or XO-CHIP (xochip) instructions (default vip)
+  --output <file>       Where to write the rom (default: the source file with a .ch8 extension)
+  --symbols <file>      Where to write the symbols (default: the source file with a .sym extension)
+
+Test options:
+  --expect <file>       The screen the rom has to end on, as text like --screenshot writes it
+  --bless               Write the screen to the --expect file instead of comparing
+  The rom runs until it's idle, and the headless options work as for run --headless.
+
+Exit codes:
+  0                     Success
+  1                     Any other error, e.g. a file couldn't be written or a test failed
+  2                     Invalid arguments, config file, rom .cfg file or rom database
+  3                     The rom couldn't be loaded
+  4                     Emulation fault, e.g. an unknown opcode";
+
+// Options that take a value, which is never mistaken for an option itself
+const VALUE_OPTIONS: [&str; 28] = [
+    "--platform", "--origin", "--speed", "--scale", "--colors", "--palette", "--seed", "--random",
+    "--database", "--config", "--keymap", "--symbols", "--frequency", "--volume", "--waveform",
+    "--wav", "--record", "--replay", "--rewind", "--rewind-speed", "--fast-forward", "--frames",
+    "--until", "--keys", "--screenshot", "--registers", "--output", "--expect",
+];
+
+/// Why a command failed. Each reason has its own exit code, so scripts can tell them apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Failure {
+    Error, // Anything not covered below, like an output file that couldn't be written
+    Usage, // Invalid arguments, config file, rom .cfg file or rom database
+    Load, // The rom couldn't be loaded
+    Fault // The program hit an emulation fault
+}
+
+impl Failure {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::Error => 1,
+            Failure::Usage => 2,
+            Failure::Load => 3,
+            Failure::Fault => 4,
+        }
+    }
+}
+
+pub enum Command {
+    Run(Options),
+    Disasm(DisasmOptions),
+    Asm(AsmOptions),
+    Info(InfoOptions),
+    Test(TestOptions),
+    Headless(HeadlessOptions),
+    Help,
+    Version
+}
+
+impl Command {
+    /// Parses the command line arguments, without the program name. Anything they don't set
+    /// comes from the config file.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        // --help and --version win over everything else, unless they're the value of an option
+        let mut flags = args.iter();
+        while let Some(arg) = flags.next() {
+            match arg.as_str() {
+                "--help" | "-h" => return Ok(Command::Help),
+                "--version" | "-V" => return Ok(Command::Version),
+                option if VALUE_OPTIONS.contains(&option) => {
+                    flags.next();
+                },
+                _ => {},
+            }
+        }
+
+        let config = || Config::load(config_flag(args)).map_err(|e| format!("Could not load config: {}", e));
+
+        match args.first().map(String::as_str) {
+            None => Err("No rom given".to_string()),
+            Some("help") => Ok(Command::Help),
+            Some("disasm") => DisasmOptions::parse(&args[1..]).map(Command::Disasm),
+            Some("asm") => AsmOptions::parse(&args[1..]).map(Command::Asm),
+            Some("info") => InfoOptions::parse(&args[1..], &config()?).map(Command::Info),
+            Some("test") => TestOptions::parse(&args[1..], &config()?).map(Command::Test),
+            Some("headless") => HeadlessOptions::parse(&args[1..], &config()?).map(Command::Headless),
+            Some("run") => Command::parse_run(&args[1..], &config()?),
+            _ => Command::parse_run(args, &config()?),
+        }
+    }
+
+    fn parse_run(args: &[String], config: &Config) -> Result<Self, String> {
+        if !args.iter().any(|arg| arg == "--headless") {
+            return Options::parse(args, config).map(Command::Run);
+        }
+
+        let args: Vec<String> = args.iter().filter(|arg| *arg != "--headless").cloned().collect();
+        HeadlessOptions::parse(&args, config).map(Command::Headless)
+    }
+}
+
+pub struct DisasmOptions {
+    pub rom: String,
+    pub recursive: bool,
+    pub platform: Quirks,
+    pub origin: usize,
+    pub output: Option<String>
+}
+
+impl DisasmOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut rom = None;
+        let mut options = DisasmOptions {
+            rom: String::new(),
+            recursive: false,
+            platform: Quirks::default(),
+            origin: 0x200,
+            output: None
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+            match arg.as_str() {
+                "--recursive" => options.recursive = true,
+                "--platform" => {
+                    options.platform = Quirks::preset(value()?)
+                        .ok_or(format!("--platform must be one of {}", Quirks::PRESET_NAMES.join(", ")))?;
+                },
+                "--origin" => options.origin = parse_origin(value()?)?,
+                "--output" => options.output = Some(value()?.clone()),
+                flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+                path => {
+                    if rom.is_some() {
+                        return Err(format!("Unexpected argument {}", path));
+                    }
+                    rom = Some(path.to_string());
+                },
+            }
+        }
+
+        options.rom = rom.ok_or("No rom given")?;
+        Ok(options)
+    }
+}
+
+pub struct AsmOptions {
+    pub source: String,
+    pub target: Target,
+    pub output: String,
+    pub symbols: String
+}
+
+impl AsmOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut source = None;
+        let mut target = Target::Chip8;
+        let mut output = None;
+        let mut symbols = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+            match arg.as_str() {
+                "--platform" => {
+                    target = Target::parse(value()?)
+                        .ok_or(format!("--platform must be one of {}", Quirks::PRESET_NAMES.join(", ")))?;
+                },
+                "--output" => output = Some(value()?.clone()),
+                "--symbols" => symbols = Some(value()?.clone()),
+                flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+                path => {
+                    if source.is_some() {
+                        return Err(format!("Unexpected argument {}", path));
+                    }
+                    source = Some(path.to_string());
+                },
+            }
+        }
+
+        let source: String = source.ok_or("No source file given")?;
+        let with_extension = |extension: &str| Path::new(&source).with_extension(extension).display().to_string();
+
+        Ok(AsmOptions {
+            output: output.unwrap_or_else(|| with_extension("ch8")),
+            symbols: symbols.unwrap_or_else(|| with_extension("sym")),
+            source,
+            target
+        })
+    }
+}
+
+pub struct InfoOptions {
+    pub rom: String,
+    pub database: Option<String>
+}
+
+impl InfoOptions {
+    fn parse(args: &[String], config: &Config) -> Result<Self, String> {
+        let mut rom = None;
+        let mut database = config.database.clone();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+            match arg.as_str() {
+                "--database" => database = Some(value()?.clone()),
+                "--config" => { value()?; }, // Already loaded
+                flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+                path => {
+                    if rom.is_some() {
+                        return Err(format!("Unexpected argument {}", path));
+                    }
+                    rom = Some(path.to_string());
+                },
+            }
+        }
+
+        Ok(InfoOptions { rom: rom.ok_or("No rom given")?, database })
+    }
+}
+
+pub struct TestOptions {
+    pub run: HeadlessOptions,
+    pub expect: String,
+    pub bless: bool
+}
+
+impl TestOptions {
+    fn parse(args: &[String], config: &Config) -> Result<Self, String> {
+        let mut expect = None;
+        let mut bless = false;
+        let mut rest = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--expect" => expect = Some(args.next().ok_or("--expect needs a value")?.clone()),
+                "--bless" => bless = true,
+                _ => rest.push(arg.clone()),
+            }
+        }
+
+        let mut run = HeadlessOptions::parse(&rest, config)?;
+        run.until = run.until.or(Some(Until::Idle)); // Test roms end by jumping to themselves
+
+        Ok(TestOptions { run, expect: expect.ok_or("test needs --expect <file>")?, bless })
+    }
+}
+
+pub struct HeadlessOptions {
+    pub rom: String,
+    pub platform: Option<Quirks>,
+    pub origin: usize,
+    pub speed: Option<CpuSpeed>,
+    pub database: Option<String>,
+    pub seed: u64,
+    pub random: RandomMode,
+    pub palette: Option<Palette>,
+    pub defaults: Defaults,
+    pub frames: u64,
+    pub until: Option<Until>,
+    pub keys: Option<String>,
+    pub screenshot: Option<String>,
+    pub registers: Option<String>
+}
+
+impl HeadlessOptions {
+    fn parse(args: &[String], config: &Config) -> Result<Self, String> {
+        let mut rom = None;
+        let mut options = HeadlessOptions {
+            rom: String::new(),
+            platform: None,
+            origin: PROGRAM_START,
+            speed: None,
+            database: config.database.clone(),
+            seed: 0, // Headless runs are reproducible by default
+            random: RandomMode::Xorshift,
+            palette: None,
+            defaults: config.defaults,
+            frames: 600,
+            until: None,
+            keys: None,
+            screenshot: None,
+            registers: None
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+            match arg.as_str() {
+                "--platform" => {
+                    options.platform = Some(Quirks::preset(value()?)
+                        .ok_or(format!("--platform must be one of {}", Quirks::PRESET_NAMES.join(", ")))?);
+                },
+                "--origin" => options.origin = parse_origin(value()?)?,
+                "--speed" => options.speed = Some(parse_speed(value()?)?),
+                "--database" => options.database = Some(value()?.clone()),
+                "--config" => { value()?; }, // Already loaded
+                "--seed" => options.seed = value()?.parse::<u64>().map_err(|_| "--seed must be a whole number")?,
+                "--random" => {
+                    options.random = RandomMode::parse(value()?).ok_or("--random must be xorshift or vip")?;
+                },
+                "--colors" | "--palette" => {
+                    options.palette = Some(Palette::parse(value()?).map_err(|e| format!("{}: {}", arg, e))?);
+                },
+                "--mute" => {}, // Headless runs are always silent
+                "--frames" => options.frames = value()?.parse::<u64>().map_err(|_| "--frames must be a whole number")?,
+                "--until" => {
+                    options.until = Some(Until::parse(value()?).ok_or("--until must be exit, idle or pc=<addr>")?);
+                },
+                "--keys" => options.keys = Some(value()?.clone()),
+                "--screenshot" => options.screenshot = Some(value()?.clone()),
+                "--registers" => options.registers = Some(value()?.clone()),
+                flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+                path => {
+                    if rom.is_some() {
+                        return Err(format!("Unexpected argument {}", path));
+                    }
+                    rom = Some(path.to_string());
+                },
+            }
+        }
+
+        options.rom = rom.ok_or("No rom given")?;
+        Ok(options)
+    }
+}
+
+/// How fast the fast-forward hotkey runs the emulator.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FastForward {
+    Rate(f64), // Times real time
+    Uncapped // As fast as the machine can go
+}
+
+impl FastForward {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "uncapped" => Some(FastForward::Uncapped),
+            rate => rate.parse::<f64>().ok().filter(|rate| *rate > 1.0).map(FastForward::Rate),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "gui"), allow(dead_code))] // Some settings are only used by the window
+pub struct Options {
+    pub rom: String,
+    pub platform: Option<Quirks>,
+    pub origin: usize,
+    pub speed: Option<CpuSpeed>,
+    pub database: Option<String>,
+    pub keymap: Option<String>,
+    pub keys: Vec<(u8, String)>,
+    pub debug: bool,
+    pub symbols: Option<String>,
+    pub mute: bool,
+    pub tone: ToneSettings,
+    pub wav: Option<String>,
+    pub palette: Option<Palette>,
+    pub defaults: Defaults,
+    pub scale: u32,
+    pub fullscreen: bool,
+    pub seed: Option<u64>,
+    pub random: RandomMode,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub rewind_seconds: usize,
+    pub rewind_speed: usize,
+    pub fast_forward: FastForward,
+    pub saves: Option<String>
+}
+
+impl Options {
+    fn parse(args: &[String], config: &Config) -> Result<Self, String> {
+        let mut rom = None;
+        let mut options = Options {
+            rom: String::new(),
+            platform: None,
+            origin: PROGRAM_START,
+            speed: None,
+            database: config.database.clone(),
+            keymap: config.keymap.clone(),
+            keys: config.keys.clone(),
+            debug: false,
+            symbols: None,
+            mute: config.mute,
+            tone: config.tone,
+            wav: None,
+            palette: None,
+            defaults: config.defaults,
+            scale: config.scale,
+            fullscreen: config.fullscreen,
+            seed: None,
+            random: RandomMode::Xorshift,
+            record: None,
+            replay: None,
+            rewind_seconds: config.rewind_seconds,
+            rewind_speed: config.rewind_speed,
+            fast_forward: config.fast_forward,
+            saves: config.saves.clone()
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+            match arg.as_str() {
+                "--platform" => {
+                    options.platform = Some(Quirks::preset(value()?)
+                        .ok_or(format!("--platform must be one of {}", Quirks::PRESET_NAMES.join(", ")))?);
+                },
+                "--origin" => options.origin = parse_origin(value()?)?,
+                "--speed" => options.speed = Some(parse_speed(value()?)?),
+                "--database" => options.database = Some(value()?.clone()),
+                "--config" => { value()?; }, // Already loaded
+                "--keymap" => options.keymap = Some(value()?.clone()),
+                "--scale" => {
+                    options.scale = value()?.parse::<u32>()
+                        .ok()
+                        .filter(|scale| (1..=MAX_SCALE).contains(scale))
+                        .ok_or(format!("--scale must be a whole number between 1 and {}", MAX_SCALE))?;
+                },
+                "--fullscreen" => options.fullscreen = true,
+                "--debug" => options.debug = true,
+                "--symbols" => options.symbols = Some(value()?.clone()),
+                "--mute" => options.mute = true,
+                "--frequency" => {
+                    options.tone.frequency = value()?.parse::<f32>()
+                        .ok()
+                        .filter(|hz| *hz > 0.0)
+                        .ok_or("--frequency must be a positive number")?;
+                },
+                "--volume" => {
+                    options.tone.volume = value()?.parse::<f32>()
+                        .ok()
+                        .filter(|volume| (0.0..=1.0).contains(volume))
+                        .ok_or("--volume must be between 0 and 1")?;
+                },
+                "--waveform" => {
+                    options.tone.waveform = Waveform::parse(value()?)
+                        .ok_or("--waveform must be square, sine, triangle or sawtooth")?;
+                },
+                "--wav" => options.wav = Some(value()?.clone()),
+                "--colors" | "--palette" => {
+                    options.palette = Some(Palette::parse(value()?).map_err(|e| format!("{}: {}", arg, e))?);
+                },
+                "--seed" => {
+                    options.seed = Some(value()?.parse::<u64>().map_err(|_| "--seed must be a whole number")?);
+                },
+                "--random" => {
+                    options.random = RandomMode::parse(value()?).ok_or("--random must be xorshift or vip")?;
+                },
+                "--record" => options.record = Some(value()?.clone()),
+                "--replay" => options.replay = Some(value()?.clone()),
+                "--rewind" => {
+                    options.rewind_seconds = value()?.parse::<usize>()
+                        .map_err(|_| "--rewind must be a whole number of seconds")?;
+                },
+                "--rewind-speed" => {
+                    options.rewind_speed = value()?.parse::<usize>()
+                        .ok()
+                        .filter(|speed| *speed > 0)
+                        .ok_or("--rewind-speed must be a positive whole number")?;
+                },
+                "--fast-forward" => {
+                    options.fast_forward = FastForward::parse(value()?)
+                        .ok_or("--fast-forward must be a number above 1 or uncapped")?;
+                },
+                flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+                path => {
+                    if rom.is_some() {
+                        return Err(format!("Unexpected argument {}", path));
+                    }
+                    rom = Some(path.to_string());
+                },
+            }
+        }
+
+        if options.record.is_some() && options.replay.is_some() {
+            return Err("--record and --replay can't be used together".to_string());
+        }
+
+        options.rom = rom.ok_or("No rom given")?;
+        if options.rom == "-" && options.debug {
+            return Err("--debug reads commands from stdin, so the rom can't be read from it too".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn parse_speed(value: &str) -> Result<CpuSpeed, String> {
+    CpuSpeed::parse(value).ok_or(format!(
+        "--speed must be vip or between {} and {} instructions per second",
+        SPEED_RANGE.start(),
+        SPEED_RANGE.end()
+    ))
+}
+
+fn parse_origin(value: &str) -> Result<usize, String> {
+    usize::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("--origin must be a hex address, got {}", value))
+}
+
+/// The config file given with `--config`, if any.
+pub fn config_flag(args: &[String]) -> Option<&str> {
+    let position = args.iter().position(|arg| arg == "--config")?;
+    args.get(position + 1).map(String::as_str)
+}
+
+/// Reads a rom from a file, or from stdin if the path is `-`. It's read before it's loaded, since
+/// its hash picks the platform, and the platform how much memory there is to load it into.
+pub fn read_rom(path: &str) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut rom = Vec::new();
+        io::stdin().lock().read_to_end(&mut rom)?;
+        Ok(rom)
+    } else {
+        fs::read(path)
+    }
+}
+
+/// Loads the bundled rom database, with the roms of a local database file on top of it.
+pub fn load_database(path: Option<&str>) -> Result<Database, String> {
+    let mut database = Database::bundled();
+    if let Some(path) = path {
+        database.merge(Database::load(path)?);
+    }
+
+    Ok(database)
+}
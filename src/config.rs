@@ -0,0 +1,339 @@
+use std::{env, fs, path::PathBuf};
+
+use chip8::audio::{ToneSettings, Waveform};
+use chip8::frame::{CpuSpeed, SPEED_RANGE};
+use chip8::palette::Palette;
+use chip8::processor::RAM_SIZE;
+use chip8::quirks::{MemoryIncrement, Quirks};
+
+use toml::{Table, Value};
+
+use crate::cli::FastForward;
+
+/// Settings for roms that neither their `.cfg` file nor the rom database has settings for.
+#[derive(Clone, Copy, Default)]
+pub struct Defaults {
+    pub quirks: Quirks,
+    pub speed: CpuSpeed,
+    pub palette: Palette
+}
+
+/// Emulator-wide defaults from `config.toml` in the XDG config directory, usually
+/// `~/.config/chip8/config.toml`. Options given on the command line win over it.
+///
+/// ```toml
+/// [display]
+/// scale = 12
+/// palette = "000000,33FF66,AAAAAA,555555"
+///
+/// [timing]
+/// speed = 1000
+///
+/// [quirks]
+/// platform = "schip"
+/// clip_sprites = false
+/// ```
+pub struct Config {
+    pub defaults: Defaults,
+    pub scale: u32, // Window pixels per CHIP-8 pixel
+    pub fullscreen: bool,
+    pub mute: bool,
+    pub tone: ToneSettings,
+    pub keymap: Option<String>,
+    pub keys: Vec<(u8, String)>, // Hex key and the name of a keyboard key bound to it
+    pub fast_forward: FastForward,
+    pub rewind_seconds: usize,
+    pub rewind_speed: usize,
+    pub database: Option<String>,
+    pub saves: Option<String>, // Directory for save states, instead of next to the rom
+    pub given: Vec<String> // The settings the file set, as `<section>.<key>`
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            defaults: Defaults::default(),
+            scale: 10,
+            fullscreen: false,
+            mute: false,
+            tone: ToneSettings::default(),
+            keymap: None,
+            keys: Vec::new(),
+            fast_forward: FastForward::Rate(4.0),
+            rewind_seconds: 10,
+            rewind_speed: 1,
+            database: None,
+            saves: None,
+            given: Vec::new()
+        }
+    }
+}
+
+/// The largest `display.scale`, which is already bigger than most screens for hi-res games.
+pub const MAX_SCALE: u32 = 32;
+
+impl Config {
+    /// Loads the config file given with `--config`, or the one in the XDG config directory.
+    /// Without either, every setting has its default.
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let path = path.display().to_string();
+        let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+        Config::parse(&contents).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Parses a config file's contents. Errors name the line or the setting.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let table: Table = contents.parse().map_err(|e: toml::de::Error| {
+            let line = e.span().map_or(1, |span| contents[..span.start].matches('\n').count() + 1);
+            format!("line {}: {}", line, e.message().trim().replace('\n', ", "))
+        })?;
+
+        let mut config = Config::default();
+        for (section, settings) in &table {
+            let settings = settings.as_table().ok_or(format!("{} must be a table, e.g. [{}]", section, section))?;
+
+            // The platform sets every quirk, so it goes first and the individual quirks change it
+            let mut settings: Vec<_> = settings.iter().collect();
+            settings.sort_by_key(|(key, _)| *key != "platform");
+
+            for (key, value) in settings {
+                let field = Field { name: format!("{}.{}", section, key), value };
+                config.set(section, key, &field)?;
+                config.given.push(field.name);
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn set(&mut self, section: &str, key: &str, field: &Field) -> Result<(), String> {
+        match (section, key) {
+            ("display", "scale") => {
+                self.scale = field.integer()
+                    .filter(|scale| (1..=MAX_SCALE as i64).contains(scale))
+                    .ok_or(field.error(&format!("a whole number between 1 and {}", MAX_SCALE)))? as u32;
+            },
+            ("display", "fullscreen") => self.fullscreen = field.bool()?,
+            ("display", "palette") => {
+                self.defaults.palette = Palette::parse(field.string()?).map_err(|e| format!("{}: {}", field.name, e))?;
+            },
+            ("audio", "mute") => self.mute = field.bool()?,
+            ("audio", "frequency") => {
+                self.tone.frequency = field.float().filter(|hz| *hz > 0.0).ok_or(field.error("a positive number"))? as f32;
+            },
+            ("audio", "volume") => {
+                self.tone.volume = field.float()
+                    .filter(|volume| (0.0..=1.0).contains(volume))
+                    .ok_or(field.error("a number between 0 and 1"))? as f32;
+            },
+            ("audio", "waveform") => {
+                self.tone.waveform = Waveform::parse(field.string()?)
+                    .ok_or(field.error("square, sine, triangle or sawtooth"))?;
+            },
+            ("input", "keymap") => self.keymap = Some(field.string()?.to_string()),
+            ("input", "keys") => self.keys = parse_keys(field)?,
+            ("timing", "speed") => {
+                self.defaults.speed = field.text().as_deref().and_then(CpuSpeed::parse).ok_or(field.error(&format!(
+                    "\"vip\" or between {} and {} instructions per second",
+                    SPEED_RANGE.start(),
+                    SPEED_RANGE.end()
+                )))?;
+            },
+            ("timing", "fast_forward") => {
+                self.fast_forward = field.text().as_deref().and_then(FastForward::parse)
+                    .ok_or(field.error("a number above 1 or \"uncapped\""))?;
+            },
+            ("timing", "rewind") => {
+                self.rewind_seconds = field.integer()
+                    .and_then(|seconds| usize::try_from(seconds).ok())
+                    .ok_or(field.error("a whole number of seconds"))?;
+            },
+            ("timing", "rewind_speed") => {
+                self.rewind_speed = field.integer()
+                    .filter(|speed| *speed > 0)
+                    .and_then(|speed| usize::try_from(speed).ok())
+                    .ok_or(field.error("a positive whole number"))?;
+            },
+            ("quirks", "platform") => {
+                self.defaults.quirks = Quirks::preset(field.string()?)
+                    .ok_or(field.error(&format!("one of {}", Quirks::PRESET_NAMES.join(", "))))?;
+            },
+            ("quirks", "vf_reset") => self.defaults.quirks.vf_reset = field.bool()?,
+            ("quirks", "shift_uses_vy") => self.defaults.quirks.shift_uses_vy = field.bool()?,
+            ("quirks", "memory_increment") => {
+                self.defaults.quirks.memory_increment = MemoryIncrement::parse(field.string()?)
+                    .ok_or(field.error("\"x+1\", \"x\" or \"none\""))?;
+            },
+            ("quirks", "jump_uses_vx") => self.defaults.quirks.jump_uses_vx = field.bool()?,
+            ("quirks", "clip_sprites") => self.defaults.quirks.clip_sprites = field.bool()?,
+            ("quirks", "large_sprites") => self.defaults.quirks.large_sprites = field.bool()?,
+            ("quirks", "display_wait") => self.defaults.quirks.display_wait = field.bool()?,
+            ("quirks", "memory_size") => {
+                self.defaults.quirks.memory_size = field.integer()
+                    .filter(|size| (0x1000..=RAM_SIZE as i64).contains(size))
+                    .ok_or(field.error(&format!("a number of bytes between 4096 and {}", RAM_SIZE)))? as usize;
+            },
+            ("paths", "database") => self.database = Some(field.string()?.to_string()),
+            ("paths", "saves") => self.saves = Some(field.string()?.to_string()),
+            _ => return Err(format!("unknown setting {}", field.name)),
+        }
+
+        Ok(())
+    }
+}
+
+// `$XDG_CONFIG_HOME/chip8/config.toml`, falling back to `~/.config` as the spec says
+fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute()) // The spec says relative paths are invalid and should be ignored
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_home.join("chip8").join("config.toml"))
+}
+
+// A setting and its value, named `<section>.<key>` in errors
+struct Field<'a> {
+    name: String,
+    value: &'a Value
+}
+
+impl Field<'_> {
+    fn error(&self, expected: &str) -> String {
+        format!("{} must be {}", self.name, expected)
+    }
+
+    fn bool(&self) -> Result<bool, String> {
+        self.value.as_bool().ok_or(self.error("true or false"))
+    }
+
+    fn string(&self) -> Result<&str, String> {
+        self.value.as_str().ok_or(self.error("a string"))
+    }
+
+    fn integer(&self) -> Option<i64> {
+        self.value.as_integer()
+    }
+
+    // Whole numbers are fine where a fraction is allowed, e.g. `frequency = 440`
+    fn float(&self) -> Option<f64> {
+        self.value.as_float().or(self.value.as_integer().map(|value| value as f64))
+    }
+
+    // A string or number as text, for settings that take either, like `speed = "vip"` or `speed = 600`
+    fn text(&self) -> Option<String> {
+        match self.value {
+            Value::String(value) => Some(value.clone()),
+            Value::Integer(value) => Some(value.to_string()),
+            Value::Float(value) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Keyboard keys that can be bound to hex keys, by their winit name. The window pairs them with
+/// winit's key codes in the same order.
+pub const KEY_NAMES: [&str; 66] = [
+    "Digit0", "Digit1", "Digit2", "Digit3", "Digit4",
+    "Digit5", "Digit6", "Digit7", "Digit8", "Digit9",
+    "KeyA", "KeyB", "KeyC", "KeyD", "KeyE", "KeyF",
+    "KeyG", "KeyH", "KeyI", "KeyJ", "KeyK", "KeyL",
+    "KeyM", "KeyN", "KeyO", "KeyP", "KeyQ", "KeyR",
+    "KeyS", "KeyT", "KeyU", "KeyV", "KeyW", "KeyX",
+    "KeyY", "KeyZ",
+    "Numpad0", "Numpad1", "Numpad2", "Numpad3", "Numpad4",
+    "Numpad5", "Numpad6", "Numpad7", "Numpad8", "Numpad9",
+    "NumpadAdd", "NumpadSubtract", "NumpadMultiply", "NumpadDivide",
+    "NumpadDecimal", "NumpadEnter",
+    "ArrowUp", "ArrowDown", "ArrowLeft", "ArrowRight",
+    "Space", "Enter", "Tab", "Backspace",
+    "Comma", "Period", "Slash", "Semicolon",
+    "ShiftLeft", "ShiftRight",
+];
+
+// A table of hex keys and the keyboard keys bound to them, e.g. `{ 5 = "ArrowUp", 6 = ["Space", "KeyJ"] }`
+fn parse_keys(field: &Field) -> Result<Vec<(u8, String)>, String> {
+    let table = field.value.as_table().ok_or(field.error("a table of hex keys and key names"))?;
+
+    let mut keys = Vec::new();
+    for (hex, names) in table {
+        let key = u8::from_str_radix(hex, 16)
+            .ok()
+            .filter(|key| *key < 16)
+            .ok_or(format!("{}: `{}` is not a hex key (0-F)", field.name, hex))?;
+
+        let entry = Field { name: format!("{}.{}", field.name, hex), value: names };
+        let names = match names {
+            Value::Array(names) => names.iter()
+                .map(|name| name.as_str().ok_or(entry.error("a key name or a list of key names")))
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => vec![entry.string()?],
+        };
+        for name in names {
+            if !KEY_NAMES.contains(&name) {
+                return Err(entry.error(&format!("a key name like \"KeyQ\" or \"ArrowUp\", not `{}`", name)));
+            }
+            keys.push((key, name.to_string()));
+        }
+    }
+
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_section() {
+        let config = Config::parse(r#"
+            [display]
+            scale = 4
+            [audio]
+            frequency = 880
+            waveform = "sine"
+            [input]
+            keys = { 5 = "ArrowUp", a = ["Space", "KeyJ"] }
+            [timing]
+            speed = "vip"
+            fast_forward = "uncapped"
+            [quirks]
+            clip_sprites = false
+            memory_size = 8192
+            platform = "schip"
+            [paths]
+            saves = "/tmp/saves"
+        "#).unwrap();
+
+        assert_eq!(config.scale, 4);
+        assert_eq!(config.tone.frequency, 880.0);
+        assert_eq!(config.keys, [(5, "ArrowUp".to_string()), (0xA, "Space".to_string()), (0xA, "KeyJ".to_string())]);
+        assert_eq!(config.defaults.speed, CpuSpeed::Vip);
+        assert!(config.fast_forward == FastForward::Uncapped);
+        assert_eq!(config.defaults.quirks, Quirks { clip_sprites: false, memory_size: 8192, ..Quirks::SCHIP });
+        assert_eq!(config.saves.as_deref(), Some("/tmp/saves"));
+    }
+
+    #[test]
+    fn errors_name_the_field() {
+        let error = |contents: &str| Config::parse(contents).err().unwrap();
+
+        assert_eq!(error("[display]\nscale = 0"), "display.scale must be a whole number between 1 and 32");
+        assert_eq!(error("[audio]\nvolume = 2.0"), "audio.volume must be a number between 0 and 1");
+        assert_eq!(error("[timing]\nspeed = \"fast\""), "timing.speed must be \"vip\" or between 1 and 1000000 instructions per second");
+        assert_eq!(error("[quirks]\nvf_reset = \"yes\""), "quirks.vf_reset must be true or false");
+        assert_eq!(error("[quirks]\nmemory_size = 100"), "quirks.memory_size must be a number of bytes between 4096 and 65536");
+        assert_eq!(error("[input]\nkeys = { 5 = 3 }"), "input.keys.5 must be a string");
+        assert_eq!(error("[input]\nkeys = { a = \"Up\" }"), "input.keys.a must be a key name like \"KeyQ\" or \"ArrowUp\", not `Up`");
+        assert_eq!(error("[input]\nkeys = { 6 = [\"Space\", \"J\"] }"), "input.keys.6 must be a key name like \"KeyQ\" or \"ArrowUp\", not `J`");
+        assert_eq!(error("[display]\ncolour = 1"), "unknown setting display.colour");
+    }
+}
@@ -0,0 +1,348 @@
+use std::{collections::HashMap, fs};
+
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::frame::{CpuSpeed, FRAME_RATE, SPEED_RANGE};
+use crate::palette::{self, Palette};
+use crate::quirks::{MemoryIncrement, Quirks};
+
+// Roms known to the emulator itself. The repository ships an empty list, which is meant to be
+// replaced with `database/programs.json` from the CHIP-8 community database
+// (https://github.com/chip-8/chip-8-database), see `database/README.md`
+const BUNDLED: &str = include_str!("../database/programs.json");
+
+/// Which hex keys a rom uses for each direction and its action buttons, so frontends can bind
+/// them to keys that make sense for the game.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeyLayout {
+    /// Moves up.
+    pub up: Option<u8>,
+    /// Moves down.
+    pub down: Option<u8>,
+    /// Moves left.
+    pub left: Option<u8>,
+    /// Moves right.
+    pub right: Option<u8>,
+    /// The main action, e.g. fire or jump.
+    pub a: Option<u8>,
+    /// The secondary action.
+    pub b: Option<u8>
+}
+
+/// The recommended settings for a rom. Anything the database doesn't say is `None`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    /// The program's title.
+    pub title: String,
+    /// The platform the rom was written for, with any quirks that differ from it.
+    pub quirks: Option<Quirks>,
+    /// The speed the rom was written for.
+    pub speed: Option<CpuSpeed>,
+    /// The keys it uses.
+    pub keys: KeyLayout,
+    /// The colors it was meant to be shown in.
+    pub palette: Option<Palette>
+}
+
+/// Settings for known roms, looked up by the SHA-1 hash of the rom.
+///
+/// Databases use the format of `programs.json` in the CHIP-8 community database: a list of
+/// programs, each with the roms it was released as, keyed by their SHA-1 hash:
+/// ```json
+/// [{
+///     "title": "Pong",
+///     "roms": {
+///         "<sha1>": {
+///             "platforms": ["originalChip8"],
+///             "tickrate": 15,
+///             "quirkyPlatforms": { "originalChip8": { "vblank": false } },
+///             "keys": { "up": 1, "down": 4 },
+///             "colors": { "pixels": ["#000000", "#ffffff"] }
+///         }
+///     }
+/// }]
+/// ```
+/// Anything else in the file is ignored, as are platforms this emulator doesn't have.
+#[derive(Default)]
+pub struct Database {
+    roms: HashMap<[u8; 20], RomInfo>
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, Rom>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u32>, // Instructions per frame
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    colors: Option<Colors>
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>
+}
+
+impl Database {
+    /// The database built into the emulator.
+    pub fn bundled() -> Self {
+        Database::parse(BUNDLED).expect("the bundled rom database is valid")
+    }
+
+    /// Loads a database file, e.g. a local one to add roms to the bundled database or override it.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Database::parse(&contents).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Parses a database. Only a file that isn't a JSON list is an error: programs and roms with
+    /// invalid settings are skipped with a warning, so one bad entry doesn't lose the rest.
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let programs: Vec<serde_json::Value> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        let mut database = Database::default();
+        for (index, program) in programs.into_iter().enumerate() {
+            let program = match Program::deserialize(program) {
+                Ok(program) => program,
+                Err(err) => {
+                    eprintln!("[-] Skipping program {} in the rom database: {}", index + 1, err);
+                    continue;
+                }
+            };
+
+            for (hash, rom) in program.roms {
+                match database.insert(&program.title, &hash, rom) {
+                    Ok(()) => {},
+                    Err(err) => eprintln!("[-] Skipping {} ({}) in the rom database: {}", program.title, hash, err),
+                }
+            }
+        }
+
+        Ok(database)
+    }
+
+    fn insert(&mut self, title: &str, hash: &str, rom: Rom) -> Result<(), String> {
+        let hash = parse_hash(hash).ok_or(format!("`{}` is not a SHA-1 hash", hash))?;
+        self.roms.insert(hash, rom.info(title)?);
+
+        Ok(())
+    }
+
+    /// Adds the roms of another database, replacing any this one already has.
+    pub fn merge(&mut self, other: Database) {
+        self.roms.extend(other.roms);
+    }
+
+    /// Looks up a rom by its SHA-1 hash, see [`hash`].
+    pub fn get(&self, hash: &[u8; 20]) -> Option<&RomInfo> {
+        self.roms.get(hash)
+    }
+}
+
+impl Rom {
+    fn info(&self, title: &str) -> Result<RomInfo, String> {
+        // The first platform listed is the one the rom was written for
+        let platform = self.platforms.iter().find_map(|id| Some((id, platform_quirks(id)?)));
+        let quirks = platform.map(|(id, mut quirks)| {
+            if let Some(overrides) = self.quirky_platforms.get(id) {
+                apply_quirks(&mut quirks, overrides);
+            }
+            quirks
+        });
+
+        let speed = self.tickrate
+            .map(|per_frame| per_frame.saturating_mul(FRAME_RATE).clamp(*SPEED_RANGE.start(), *SPEED_RANGE.end()))
+            .map(CpuSpeed::Hz);
+
+        let mut keys = KeyLayout::default();
+        for (name, key) in &self.keys {
+            let slot = match name.as_str() {
+                "up" => &mut keys.up,
+                "down" => &mut keys.down,
+                "left" => &mut keys.left,
+                "right" => &mut keys.right,
+                "a" => &mut keys.a,
+                "b" => &mut keys.b,
+                _ => continue,
+            };
+            if *key >= 16 {
+                return Err(format!("key `{}` is {}, which isn't a hex key (0-F)", name, key));
+            }
+            *slot = Some(*key);
+        }
+
+        // Roms list 2 colors, or 4 for XO-CHIP. The rest keep their defaults.
+        let palette = match &self.colors {
+            Some(colors) if !colors.pixels.is_empty() => {
+                let mut palette = Palette::default();
+                for (color, value) in palette.colors.iter_mut().zip(&colors.pixels) {
+                    *color = palette::parse_color(value)?;
+                }
+                Some(palette)
+            },
+            _ => None,
+        };
+
+        Ok(RomInfo { title: title.to_string(), quirks, speed, keys, palette })
+    }
+}
+
+// The quirks of a community database platform id, if this emulator has the platform
+fn platform_quirks(id: &str) -> Option<Quirks> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some(Quirks::COSMAC_VIP),
+        "chip48" => Some(Quirks::CHIP_48),
+        // CHIP-8 as most modern interpreters run it: like the VIP, but without its display wait and VF reset
+        "modernChip8" => Some(Quirks { vf_reset: false, display_wait: false, ..Quirks::COSMAC_VIP }),
+        "superchip1" | "superchip" => Some(Quirks::SCHIP),
+        "xochip" => Some(Quirks::XO_CHIP),
+        _ => None,
+    }
+}
+
+// Applies the quirk ids used by the community database on top of a platform's quirks
+fn apply_quirks(quirks: &mut Quirks, overrides: &HashMap<String, bool>) {
+    let get = |id: &str| overrides.get(id).copied();
+
+    if let Some(shift) = get("shift") {
+        quirks.shift_uses_vy = !shift; // The quirk is shifting Vx in place
+    }
+    match (get("memoryLeaveIUnchanged"), get("memoryIncrementByX")) {
+        (Some(true), _) => quirks.memory_increment = MemoryIncrement::None,
+        (_, Some(true)) => quirks.memory_increment = MemoryIncrement::X,
+        (Some(false), _) | (_, Some(false)) => quirks.memory_increment = MemoryIncrement::XPlusOne,
+        _ => {},
+    }
+    if let Some(wrap) = get("wrap") {
+        quirks.clip_sprites = !wrap;
+    }
+    if let Some(jump) = get("jump") {
+        quirks.jump_uses_vx = jump;
+    }
+    if let Some(vblank) = get("vblank") {
+        quirks.display_wait = vblank;
+    }
+    if let Some(logic) = get("logic") {
+        quirks.vf_reset = logic;
+    }
+}
+
+fn parse_hash(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut hash = [0u8; 20];
+    for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// The SHA-1 hash a rom is looked up by.
+pub fn hash(rom: &[u8]) -> [u8; 20] {
+    Sha1::digest(rom).into()
+}
+
+/// Formats a hash the way the database writes it, as 40 lowercase hex digits.
+pub fn format_hash(hash: &[u8; 20]) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+    const OTHER_HASH: &str = "fedcba9876543210fedcba9876543210fedcba98";
+
+    #[test]
+    fn bundled_database_parses() {
+        Database::bundled();
+    }
+
+    #[test]
+    fn reads_platform_quirks_speed_keys_and_colors() {
+        let json = format!(r##"[{{
+            "title": "Test",
+            "authors": ["Someone"],
+            "roms": {{
+                "{}": {{
+                    "file": "test.ch8",
+                    "platforms": ["superchip", "xochip"],
+                    "tickrate": 30,
+                    "quirkyPlatforms": {{ "superchip": {{ "wrap": true, "memoryIncrementByX": true }} }},
+                    "keys": {{ "up": 5, "a": 6 }},
+                    "colors": {{ "pixels": ["#102030", "#405060"], "buzzer": "#ffffff" }}
+                }}
+            }}
+        }}]"##, HASH);
+        let database = Database::parse(&json).unwrap();
+        let info = database.get(&parse_hash(HASH).unwrap()).unwrap();
+
+        let mut quirks = Quirks::SCHIP;
+        quirks.clip_sprites = false;
+        quirks.memory_increment = MemoryIncrement::X;
+        assert_eq!(info.quirks, Some(quirks));
+        assert_eq!(info.speed, Some(CpuSpeed::Hz(1800)));
+        assert_eq!(info.keys, KeyLayout { up: Some(5), a: Some(6), ..KeyLayout::default() });
+
+        let palette = info.palette.unwrap();
+        assert_eq!(palette.colors[0], [0x10, 0x20, 0x30, 0xFF]);
+        assert_eq!(palette.colors[1], [0x40, 0x50, 0x60, 0xFF]);
+        assert_eq!(palette.colors[2], Palette::default().colors[2]);
+    }
+
+    #[test]
+    fn modern_chip8_is_the_vip_without_its_display_wait_and_vf_reset() {
+        let json = format!(r#"[{{ "title": "Test", "roms": {{ "{}": {{ "platforms": ["modernChip8"] }} }} }}]"#, HASH);
+        let database = Database::parse(&json).unwrap();
+
+        assert_eq!(database.get(&parse_hash(HASH).unwrap()).unwrap().quirks, Some(Quirks {
+            vf_reset: false,
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            large_sprites: false,
+            display_wait: false,
+            memory_size: 0x1000
+        }));
+    }
+
+    #[test]
+    fn invalid_entries_are_skipped() {
+        let json = format!(r#"[
+            {{ "title": "Bad hash", "roms": {{ "1234": {{}} }} }},
+            {{ "title": "Bad key", "roms": {{ "{0}": {{ "keys": {{ "up": 16 }} }} }} }},
+            {{ "title": 5 }},
+            {{ "title": "Good", "roms": {{ "{1}": {{}} }} }}
+        ]"#, HASH, OTHER_HASH);
+        let database = Database::parse(&json).unwrap();
+
+        assert_eq!(database.roms.len(), 1);
+        assert_eq!(database.get(&parse_hash(OTHER_HASH).unwrap()).unwrap().title, "Good");
+        assert!(Database::parse("{}").is_err());
+    }
+
+    #[test]
+    fn merged_entries_replace_existing_ones() {
+        let entry = |title: &str| format!(r#"[{{ "title": "{}", "roms": {{ "{}": {{}} }} }}]"#, title, HASH);
+        let mut database = Database::parse(&entry("Bundled")).unwrap();
+        database.merge(Database::parse(&entry("Local")).unwrap());
+
+        assert_eq!(database.get(&parse_hash(HASH).unwrap()).unwrap().title, "Local");
+    }
+}
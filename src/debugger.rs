@@ -0,0 +1,512 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    io::{self, BufRead},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use crate::disasm;
+use crate::error::Chip8Error;
+use crate::processor::{Processor, State};
+use crate::symbols::Symbols;
+
+/// The commands [`Debugger::execute`] understands, for frontends to print.
+pub const HELP: &str = "Commands:
+  s, step [n]         Run n instructions (default 1)
+  n, next             Step over a subroutine call
+  f, finish           Run until the current subroutine returns
+  c, continue         Resume emulation
+  p, pause            Pause emulation
+  b, break <addr>     Set a breakpoint (addresses are hex or symbol names)
+  d, delete <addr>    Delete a breakpoint
+  w, watch <target>   Stop when a register (v0-vf, i) changes or a memory address is written
+  u, unwatch <target> Remove a watchpoint
+  l, list             List breakpoints and watchpoints
+  r, regs             Show registers, timers and the stack
+  x <addr> [len]      Show memory as hex (default 64 bytes)
+  h, help             Show this message";
+
+/// A register that can be watched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    /// One of V0 to VF.
+    V(usize),
+    /// The index register.
+    I
+}
+
+impl Register {
+    fn parse(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name == "i" {
+            return Some(Register::I);
+        }
+
+        let index = usize::from_str_radix(name.strip_prefix('v')?, 16).ok()?;
+        (index < 16).then_some(Register::V(index))
+    }
+
+    fn value(&self, processor: &Processor) -> usize {
+        match self {
+            Register::V(index) => processor.v()[*index] as usize,
+            Register::I => processor.i(),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Register::V(index) => format!("v{:x}", index),
+            Register::I => "i".to_string(),
+        }
+    }
+}
+
+/// Why the debugger paused emulation.
+#[derive(Debug, PartialEq)]
+pub enum StopReason {
+    /// The program counter reached a breakpoint at this address.
+    Breakpoint(usize),
+    /// The program wrote to a watched address.
+    MemoryWrite {
+        /// The watched address.
+        address: usize,
+        /// The byte before the write.
+        old: u8,
+        /// The byte written.
+        new: u8
+    },
+    /// A watched register changed.
+    RegisterChange {
+        /// The watched register.
+        register: Register,
+        /// Its value before the instruction.
+        old: usize,
+        /// Its value after it.
+        new: usize
+    },
+    /// A step, next or finish command completed.
+    StepDone,
+    /// The program faulted, with the error.
+    Fault(String)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Paused,
+    Running,
+    RunUntilReturn { sp: usize } // Runs until the stack pointer drops back to sp
+}
+
+/// Wraps the processor's emulation cycle with breakpoints, watchpoints and stepping.
+pub struct Debugger {
+    mode: Mode,
+    breakpoints: BTreeSet<usize>,
+    memory_watches: BTreeSet<usize>,
+    register_watches: BTreeSet<Register>,
+    stop: Option<StopReason>,
+    symbols: Symbols
+}
+
+impl Debugger {
+    /// The debugger starts paused, so breakpoints can be set before the program runs. Symbols from
+    /// the assembler are used to name addresses.
+    pub fn new(symbols: Symbols) -> Self {
+        Debugger {
+            mode: Mode::Paused,
+            breakpoints: BTreeSet::new(),
+            memory_watches: BTreeSet::new(),
+            register_watches: BTreeSet::new(),
+            stop: None,
+            symbols
+        }
+    }
+
+    /// Whether emulation is running, rather than paused.
+    pub fn is_running(&self) -> bool {
+        self.mode != Mode::Paused
+    }
+
+    /// Why emulation last stopped, if it hasn't been reported yet.
+    pub fn take_stop_reason(&mut self) -> Option<StopReason> {
+        self.stop.take()
+    }
+
+    /// Runs a single instruction, pausing if it hits a breakpoint or watchpoint.
+    pub fn tick(&mut self, processor: &mut Processor) -> Result<State, Chip8Error> {
+        let registers_before: Vec<(Register, usize)> = self.register_watches.iter()
+            .map(|register| (*register, register.value(processor)))
+            .collect();
+
+        let state = match processor.tick() {
+            Ok(state) => state,
+            Err(err) => {
+                self.pause(StopReason::Fault(err.to_string()));
+                return Err(err);
+            }
+        };
+
+        // Every store counts, even one that writes the value already there
+        for &(address, old, new) in processor.writes() {
+            if self.memory_watches.contains(&address) {
+                self.pause(StopReason::MemoryWrite { address, old, new });
+            }
+        }
+
+        for (register, old) in registers_before {
+            let new = register.value(processor);
+            if new != old {
+                self.pause(StopReason::RegisterChange { register, old, new });
+            }
+        }
+
+        if let Mode::RunUntilReturn { sp } = self.mode
+            && processor.sp() <= sp
+        {
+            self.pause(StopReason::StepDone);
+        }
+
+        if self.breakpoints.contains(&processor.pc()) {
+            self.pause(StopReason::Breakpoint(processor.pc()));
+        }
+
+        Ok(state)
+    }
+
+    fn pause(&mut self, reason: StopReason) {
+        self.mode = Mode::Paused;
+        if self.stop.is_none() { // Keep the first reason if several things happened at once
+            self.stop = Some(reason);
+        }
+    }
+
+    /// Runs a console command and returns its output.
+    pub fn execute(&mut self, line: &str, processor: &mut Processor) -> String {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return String::new();
+        };
+        let args: Vec<&str> = words.collect();
+
+        match (command, args.as_slice()) {
+            ("s" | "step", []) => self.step(processor, 1),
+            ("s" | "step", [count]) => match count.parse::<usize>() {
+                Ok(count) => self.step(processor, count),
+                Err(_) => format!("Invalid count `{}`", count),
+            },
+            ("n" | "next", []) => {
+                let opcode = read_opcode(processor, processor.pc());
+                if opcode & 0xF000 == 0x2000 {
+                    self.mode = Mode::RunUntilReturn { sp: processor.sp() };
+                    String::new()
+                } else {
+                    self.step(processor, 1)
+                }
+            },
+            ("f" | "finish", []) => {
+                if processor.sp() == 0 {
+                    return "Not inside a subroutine".to_string();
+                }
+                self.mode = Mode::RunUntilReturn { sp: processor.sp() - 1 };
+                String::new()
+            },
+            ("c" | "continue", []) => {
+                self.mode = Mode::Running;
+                String::new()
+            },
+            ("p" | "pause", []) => {
+                self.mode = Mode::Paused;
+                self.show_location(processor)
+            },
+            ("b" | "break", [address]) => match self.parse_address(address) {
+                Some(address) => {
+                    self.breakpoints.insert(address);
+                    format!("Breakpoint at {:#05x}", address)
+                },
+                None => format!("Invalid address `{}`", address),
+            },
+            ("d" | "delete", [address]) => match self.parse_address(address) {
+                Some(address) if self.breakpoints.remove(&address) => format!("Deleted breakpoint at {:#05x}", address),
+                _ => format!("No breakpoint at `{}`", address),
+            },
+            ("w" | "watch", [target]) => {
+                if let Some(register) = Register::parse(target) {
+                    self.register_watches.insert(register);
+                    format!("Watching {}", register.name())
+                } else if let Some(address) = self.parse_address(target).filter(|address| *address < processor.ram().len()) {
+                    self.memory_watches.insert(address);
+                    format!("Watching {:#05x}", address)
+                } else {
+                    format!("Invalid register or address `{}`", target)
+                }
+            },
+            ("u" | "unwatch", [target]) => {
+                let removed = match Register::parse(target) {
+                    Some(register) => self.register_watches.remove(&register),
+                    None => self.parse_address(target).is_some_and(|address| self.memory_watches.remove(&address)),
+                };
+                if removed { format!("Stopped watching {}", target) } else { format!("Not watching `{}`", target) }
+            },
+            ("l" | "list", []) => self.list(),
+            ("r" | "regs", []) => show_registers(processor),
+            ("x", [address]) => self.hex_dump(processor, address, "64"),
+            ("x", [address, length]) => self.hex_dump(processor, address, length),
+            ("h" | "help", []) => HELP.to_string(),
+            _ => format!("Unknown command `{}`, type `help` for a list of commands", line.trim()),
+        }
+    }
+
+    fn step(&mut self, processor: &mut Processor, count: usize) -> String {
+        self.mode = Mode::Running;
+        for _ in 0..count {
+            // The frame loop is paused, so no timer tick is coming to end a display wait
+            processor.skip_display_wait();
+            if self.tick(processor).is_err() || !self.is_running() {
+                break;
+            }
+        }
+        self.mode = Mode::Paused;
+
+        // A breakpoint, watchpoint or fault hit while stepping is reported by the caller
+        self.show_location(processor)
+    }
+
+    fn list(&self) -> String {
+        let mut output = String::new();
+
+        let _ = writeln!(output, "Breakpoints:");
+        for address in &self.breakpoints {
+            let _ = writeln!(output, "  {:#05x}", address);
+        }
+
+        let _ = write!(output, "Watchpoints:");
+        for register in &self.register_watches {
+            let _ = write!(output, "\n  {}", register.name());
+        }
+        for address in &self.memory_watches {
+            let _ = write!(output, "\n  {:#05x}", address);
+        }
+
+        output
+    }
+
+    fn hex_dump(&self, processor: &Processor, address: &str, length: &str) -> String {
+        let (Some(start), Ok(length)) = (self.parse_address(address), length.parse::<usize>()) else {
+            return format!("Invalid address or length `{} {}`", address, length);
+        };
+
+        let ram = processor.ram();
+        let end = start.saturating_add(length).min(ram.len());
+        let mut output = String::new();
+
+        for line_start in (start..end).step_by(16) {
+            let bytes = &ram[line_start..(line_start + 16).min(end)];
+
+            let _ = write!(output, "{:04x}:", line_start);
+            for byte in bytes {
+                let _ = write!(output, " {:02x}", byte);
+            }
+            let _ = write!(output, "{}  ", "   ".repeat(16 - bytes.len()));
+            for byte in bytes {
+                output.push(if byte.is_ascii_graphic() { *byte as char } else { '.' });
+            }
+            output.push('\n');
+        }
+
+        output.trim_end().to_string()
+    }
+
+    /// A message saying why emulation stopped and where, for the console.
+    pub fn describe(&self, reason: &StopReason, processor: &Processor) -> String {
+        let reason = match reason {
+            StopReason::Breakpoint(address) => format!("Breakpoint at {:#05x}", address),
+            StopReason::MemoryWrite { address, old, new } => {
+                format!("Memory {:#05x} changed: {:02x} -> {:02x}", address, old, new)
+            },
+            StopReason::RegisterChange { register, old, new } => {
+                format!("Register {} changed: {:#x} -> {:#x}", register.name(), old, new)
+            },
+            StopReason::StepDone => "Returned from subroutine".to_string(),
+            StopReason::Fault(err) => format!("Emulation fault: {}", err),
+        };
+
+        format!("{}\n{}", reason, self.show_location(processor))
+    }
+
+    fn show_location(&self, processor: &Processor) -> String {
+        let pc = processor.pc();
+        let mnemonic = processor.ram().get(pc..)
+            .and_then(|bytes| disasm::decode(bytes, &processor.quirks()))
+            .map_or("???".to_string(), |(mnemonic, _)| mnemonic);
+        let label = self.symbols.name(pc).map_or(String::new(), |name| format!("{}:\n", name));
+
+        format!("{}{:04x}: {:04x}  {}", label, pc, read_opcode(processor, pc), mnemonic)
+    }
+
+    // Addresses are symbol names, or hex with or without a 0x prefix
+    fn parse_address(&self, value: &str) -> Option<usize> {
+        self.symbols.address(value)
+            .or_else(|| usize::from_str_radix(value.trim_start_matches("0x"), 16).ok())
+    }
+}
+
+/// Reads commands from stdin on a background thread, so the window keeps rendering while the
+/// console waits for input.
+pub fn spawn_console() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+fn show_registers(processor: &Processor) -> String {
+    let mut output = format!(
+        "pc {:04x}  i {:04x}  sp {:x}  dt {:02x}  st {:02x}\n",
+        processor.pc(), processor.i(), processor.sp(), processor.delay_timer, processor.sound_timer
+    );
+
+    for (index, value) in processor.v().iter().enumerate() {
+        let separator = if index % 8 == 7 { "\n" } else { "  " };
+        let _ = write!(output, "v{:x} {:02x}{}", index, value, separator);
+    }
+
+    let _ = write!(output, "stack:");
+    for address in processor.stack() {
+        let _ = write!(output, " {:04x}", address);
+    }
+
+    output
+}
+
+fn read_opcode(processor: &Processor, address: usize) -> u16 {
+    let ram = processor.ram();
+    let byte = |address: usize| ram.get(address).copied().unwrap_or(0) as u16;
+
+    byte(address) << 8 | byte(address + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Quirks;
+
+    // 0x200 calls a subroutine at 0x206 that sets V1, I and stores V0-V1 at 0x300, then returns
+    // and loops forever at 0x204
+    const PROGRAM: [u16; 7] = [0x2206, 0x6001, 0x1204, 0x6105, 0xA300, 0xF155, 0x00EE];
+
+    fn setup() -> (Debugger, Processor) {
+        let rom: Vec<u8> = PROGRAM.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        let mut processor = Processor::new(Quirks::COSMAC_VIP);
+        processor.load_bytes(&rom, 0x200).unwrap();
+
+        (Debugger::new(Symbols::default()), processor)
+    }
+
+    // Ticks like the frame loop does while the debugger lets it run
+    fn run(debugger: &mut Debugger, processor: &mut Processor) -> Option<StopReason> {
+        for _ in 0..100 {
+            if !debugger.is_running() {
+                break;
+            }
+            debugger.tick(processor).unwrap();
+        }
+        debugger.take_stop_reason()
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let (mut debugger, mut processor) = setup();
+
+        debugger.execute("next", &mut processor);
+        assert_eq!(run(&mut debugger, &mut processor), Some(StopReason::StepDone));
+        assert_eq!((processor.pc(), processor.sp(), processor.v()[1]), (0x202, 0, 5));
+
+        // Anything else is a single step
+        debugger.execute("next", &mut processor);
+        assert_eq!((processor.pc(), processor.v()[0]), (0x204, 1));
+        assert!(!debugger.is_running());
+    }
+
+    #[test]
+    fn finish_runs_until_the_subroutine_returns() {
+        let (mut debugger, mut processor) = setup();
+        assert_eq!(debugger.execute("finish", &mut processor), "Not inside a subroutine");
+
+        debugger.execute("step 2", &mut processor);
+        assert_eq!((processor.pc(), processor.sp()), (0x208, 1));
+
+        debugger.execute("finish", &mut processor);
+        assert_eq!(run(&mut debugger, &mut processor), Some(StopReason::StepDone));
+        assert_eq!((processor.pc(), processor.sp()), (0x202, 0));
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_instruction() {
+        let (mut debugger, mut processor) = setup();
+
+        assert_eq!(debugger.execute("break 20a", &mut processor), "Breakpoint at 0x20a");
+        debugger.execute("continue", &mut processor);
+        assert_eq!(run(&mut debugger, &mut processor), Some(StopReason::Breakpoint(0x20A)));
+        assert_eq!(processor.pc(), 0x20A);
+
+        assert_eq!(debugger.execute("delete 20a", &mut processor), "Deleted breakpoint at 0x20a");
+        assert_eq!(debugger.execute("delete 20a", &mut processor), "No breakpoint at `20a`");
+    }
+
+    #[test]
+    fn register_watchpoints_report_the_change() {
+        let (mut debugger, mut processor) = setup();
+
+        assert_eq!(debugger.execute("watch v1", &mut processor), "Watching v1");
+        debugger.execute("continue", &mut processor);
+        assert_eq!(
+            run(&mut debugger, &mut processor),
+            Some(StopReason::RegisterChange { register: Register::V(1), old: 0, new: 5 })
+        );
+        assert_eq!(processor.pc(), 0x208);
+
+        assert_eq!(debugger.execute("unwatch v1", &mut processor), "Stopped watching v1");
+        assert_eq!(debugger.execute("watch v16", &mut processor), "Invalid register or address `v16`");
+    }
+
+    #[test]
+    fn memory_watchpoints_report_the_write() {
+        let (mut debugger, mut processor) = setup();
+
+        assert_eq!(debugger.execute("watch 301", &mut processor), "Watching 0x301");
+        debugger.execute("continue", &mut processor);
+        assert_eq!(
+            run(&mut debugger, &mut processor),
+            Some(StopReason::MemoryWrite { address: 0x301, old: 0, new: 5 })
+        );
+        assert_eq!(processor.pc(), 0x20C);
+    }
+
+    #[test]
+    fn memory_watchpoints_report_writes_of_the_same_value() {
+        let (mut debugger, mut processor) = setup();
+
+        debugger.execute("watch 300", &mut processor);
+        debugger.execute("continue", &mut processor);
+        assert_eq!(
+            run(&mut debugger, &mut processor),
+            Some(StopReason::MemoryWrite { address: 0x300, old: 0, new: 0 })
+        );
+    }
+
+    #[test]
+    fn hex_dumps_stop_at_the_end_of_memory() {
+        let (mut debugger, mut processor) = setup();
+
+        assert_eq!(debugger.execute("x 200 4", &mut processor), "0200: 22 06 60 01                                      \".`.");
+        assert_eq!(debugger.execute(&format!("x ffff {}", usize::MAX), &mut processor).lines().count(), 1);
+    }
+}
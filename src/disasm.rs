@@ -0,0 +1,292 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::processor::nibbles;
+use crate::quirks::Quirks;
+
+/// Decodes the instruction at the start of `bytes` into its mnemonic and its length in bytes.
+/// Returns None if the bytes aren't a valid instruction. The quirks decide how BNNN reads.
+pub fn decode(bytes: &[u8], quirks: &Quirks) -> Option<(String, usize)> {
+    let opcode = (*bytes.first()? as u16) << 8 | *bytes.get(1)? as u16;
+
+    let nibbles = nibbles(opcode);
+    let x = nibbles.1;
+    let y = nibbles.2;
+    let n = nibbles.3;
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    let mnemonic = match nibbles {
+        (0x0, 0x0, 0x0, 0x0) => "NOP".to_string(),
+        (0x0, 0x0, 0xC, _) => format!("SCD {}", n),
+        (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
+        (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
+        (0x0, 0x0, 0xF, 0xB) => "SCR".to_string(),
+        (0x0, 0x0, 0xF, 0xC) => "SCL".to_string(),
+        (0x0, 0x0, 0xF, 0xD) => "EXIT".to_string(),
+        (0x0, 0x0, 0xF, 0xE) => "LOW".to_string(),
+        (0x0, 0x0, 0xF, 0xF) => "HIGH".to_string(),
+        (0x1, _, _, _) => format!("JP {:#05x}", nnn),
+        (0x2, _, _, _) => format!("CALL {:#05x}", nnn),
+        (0x3, _, _, _) => format!("SE V{:X}, {:#04x}", x, nn),
+        (0x4, _, _, _) => format!("SNE V{:X}, {:#04x}", x, nn),
+        (0x5, _, _, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+        (0x5, _, _, 0x2) => format!("SAVE V{:X}, V{:X}", x, y),
+        (0x5, _, _, 0x3) => format!("LOAD V{:X}, V{:X}", x, y),
+        (0x6, _, _, _) => format!("LD V{:X}, {:#04x}", x, nn),
+        (0x7, _, _, _) => format!("ADD V{:X}, {:#04x}", x, nn),
+        (0x8, _, _, 0x0) => format!("LD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x3) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x4) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x5) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x6) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x9, _, _, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _, _, _) => format!("LD I, {:#05x}", nnn),
+        (0xB, _, _, _) if quirks.jump_uses_vx => format!("JP V{:X}, {:#05x}", x, nnn),
+        (0xB, _, _, _) => format!("JP V0, {:#05x}", nnn),
+        (0xC, _, _, _) => format!("RND V{:X}, {:#04x}", x, nn),
+        (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 0x9, 0xE) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA, 0x1) => format!("SKNP V{:X}", x),
+        (0xF, 0x0, 0x0, 0x0) => {
+            let address = (*bytes.get(2)? as u16) << 8 | *bytes.get(3)? as u16;
+            return Some((format!("LD I, LONG {:#06x}", address), 4));
+        },
+        (0xF, _, 0x0, 0x1) => format!("PLANE {}", x),
+        (0xF, 0x0, 0x0, 0x2) => "AUDIO".to_string(),
+        (0xF, _, 0x0, 0x7) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0x0, 0xA) => format!("LD V{:X}, K", x),
+        (0xF, _, 0x1, 0x5) => format!("LD DT, V{:X}", x),
+        (0xF, _, 0x1, 0x8) => format!("LD ST, V{:X}", x),
+        (0xF, _, 0x1, 0xE) => format!("ADD I, V{:X}", x),
+        (0xF, _, 0x2, 0x9) => format!("LD F, V{:X}", x),
+        (0xF, _, 0x3, 0x0) => format!("LD HF, V{:X}", x),
+        (0xF, _, 0x3, 0xA) => format!("PITCH V{:X}", x),
+        (0xF, _, 0x3, 0x3) => format!("LD B, V{:X}", x),
+        (0xF, _, 0x5, 0x5) => format!("LD [I], V{:X}", x),
+        (0xF, _, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+        (0xF, _, 0x7, 0x5) => format!("LD R, V{:X}", x),
+        (0xF, _, 0x8, 0x5) => format!("LD V{:X}, R", x),
+        (_, _, _, _) => return None,
+    };
+
+    Some((mnemonic, 2))
+}
+
+/// Turns a ROM loaded at `origin` into assembly text, one instruction or data byte per line.
+///
+/// In linear mode every pair of bytes is decoded as an instruction. In recursive mode only bytes
+/// reachable from the entry point (by following jumps, calls and skips) are decoded, and
+/// everything else is shown as data.
+pub fn disassemble(rom: &[u8], origin: usize, recursive: bool, quirks: &Quirks) -> String {
+    let (code, labels) = if recursive {
+        trace(rom, origin, quirks)
+    } else {
+        (linear(rom, quirks), BTreeMap::new())
+    };
+
+    let mut output = String::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        let address = origin + offset;
+        if let Some(label) = labels.get(&address) {
+            let _ = writeln!(output, "\n{}:", label);
+        }
+
+        if code.contains(&offset)
+            && let Some((mnemonic, length)) = decode(&rom[offset..], quirks)
+        {
+            let bytes = &rom[offset..offset + length];
+            let _ = writeln!(output, "{:04x}: {:<12} {}", address, hex_bytes(bytes), mnemonic);
+            offset += length;
+        } else {
+            let byte = rom[offset];
+            let _ = writeln!(output, "{:04x}: {:<12} db {:#04x}  ; {}", address, hex_bytes(&[byte]), byte, sprite_row(byte));
+            offset += 1;
+        }
+    }
+
+    output
+}
+
+// Decodes everything from the start of the rom, two bytes at a time
+fn linear(rom: &[u8], quirks: &Quirks) -> BTreeSet<usize> {
+    let mut code = BTreeSet::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        match decode(&rom[offset..], quirks) {
+            Some((_, length)) => {
+                code.insert(offset);
+                offset += length;
+            },
+            None => offset += 2, // Shown as data
+        }
+    }
+
+    code
+}
+
+// Follows the control flow from the entry point. Returns the offsets that start an instruction,
+// and labels for the addresses that are jumped to or called.
+fn trace(rom: &[u8], origin: usize, quirks: &Quirks) -> (BTreeSet<usize>, BTreeMap<usize, String>) {
+    let mut code = BTreeSet::new();
+    let mut labels = BTreeMap::new();
+    let mut pending = vec![origin];
+
+    while let Some(address) = pending.pop() {
+        let Some(offset) = address.checked_sub(origin).filter(|offset| *offset < rom.len()) else {
+            continue; // Outside the rom, e.g. a jump into the font
+        };
+        if code.contains(&offset) {
+            continue;
+        }
+        let Some((_, length)) = decode(&rom[offset..], quirks) else {
+            continue; // Not an instruction, so whatever led here was probably data
+        };
+        code.insert(offset);
+
+        let opcode = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+        let nnn = (opcode & 0x0FFF) as usize;
+        let next = address + length;
+
+        match nibbles(opcode) {
+            (0x1, _, _, _) => {
+                labels.entry(nnn).or_insert(format!("loc_{:03x}", nnn));
+                pending.push(nnn);
+            },
+            (0x2, _, _, _) => {
+                labels.insert(nnn, format!("sub_{:03x}", nnn));
+                pending.push(nnn);
+                pending.push(next);
+            },
+            (0xB, _, _, _) => { // The target depends on a register, so only the base is known
+                labels.entry(nnn).or_insert(format!("table_{:03x}", nnn));
+                pending.push(nnn);
+            },
+            (0x0, 0x0, 0xE, 0xE) | (0x0, 0x0, 0xF, 0xD) => {}, // Return and exit end the flow
+            (0x3, _, _, _) | (0x4, _, _, _) | (0x5, _, _, 0x0) | (0x9, _, _, 0x0) |
+            (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1) => {
+                // Either the next instruction runs or it's skipped. Long I loads are four bytes.
+                let skipped = rom.get(next - origin..)
+                    .and_then(|bytes| decode(bytes, quirks))
+                    .map_or(2, |(_, length)| length);
+                pending.push(next);
+                pending.push(next + skipped);
+            },
+            _ => pending.push(next),
+        }
+    }
+
+    (code, labels)
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+// Data is often sprites, so show each byte as a row of pixels
+fn sprite_row(byte: u8) -> String {
+    (0..8).map(|bit| if byte & (0b1000_0000 >> bit) != 0 { '#' } else { '.' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(opcodes: &[u16]) -> Vec<u8> {
+        opcodes.iter().flat_map(|opcode| opcode.to_be_bytes()).collect()
+    }
+
+    // The address and mnemonic (or data) of each line, without the bytes
+    fn lines(assembly: &str) -> Vec<String> {
+        assembly.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| match line.split_once(": ") {
+                Some((address, rest)) => format!("{} {}", address, rest.get(13..).unwrap_or("").trim()),
+                None => line.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_instructions_and_their_lengths() {
+        let quirks = Quirks::default();
+
+        assert_eq!(decode(&[0x00, 0xE0], &quirks), Some(("CLS".to_string(), 2)));
+        assert_eq!(decode(&[0xD1, 0x25], &quirks), Some(("DRW V1, V2, 5".to_string(), 2)));
+        assert_eq!(decode(&[0xF0, 0x00, 0x12, 0x34], &quirks), Some(("LD I, LONG 0x1234".to_string(), 4)));
+        assert_eq!(decode(&[0xF0, 0x00, 0x12], &quirks), None);
+        assert_eq!(decode(&[0x81, 0x2F], &quirks), None);
+        assert_eq!(decode(&[0x12], &quirks), None);
+    }
+
+    #[test]
+    fn jump_with_offset_follows_the_quirk() {
+        assert_eq!(decode(&[0xB3, 0x45], &Quirks::COSMAC_VIP).unwrap().0, "JP V0, 0x345");
+        assert_eq!(decode(&[0xB3, 0x45], &Quirks::CHIP_48).unwrap().0, "JP V3, 0x345");
+    }
+
+    #[test]
+    fn linear_mode_decodes_everything() {
+        let assembly = disassemble(&rom(&[0x1202, 0x6001, 0x812F]), 0x200, false, &Quirks::default());
+
+        assert_eq!(lines(&assembly), [
+            "0200 JP 0x202",
+            "0202 LD V0, 0x01",
+            "0204 db 0x81  ; #......#",
+            "0205 db 0x2f  ; ..#.####",
+        ]);
+    }
+
+    #[test]
+    fn tracing_labels_targets_and_leaves_data_after_jumps() {
+        // A call, a loop, a sprite after the loop's jump and the subroutine
+        let assembly = disassemble(&rom(&[0x2206, 0x1202, 0x6001, 0x00EE]), 0x200, true, &Quirks::default());
+
+        assert_eq!(lines(&assembly), [
+            "0200 CALL 0x206",
+            "loc_202:",
+            "0202 JP 0x202",
+            "0204 db 0x60  ; .##.....",
+            "0205 db 0x01  ; .......#",
+            "sub_206:",
+            "0206 RET",
+        ]);
+    }
+
+    #[test]
+    fn tracing_follows_skips_over_long_loads() {
+        // The skip lands after all four bytes of the long load, not in the middle of it
+        let assembly = disassemble(&rom(&[0x3000, 0xF000, 0x1234, 0x1208, 0x00FD]), 0x200, true, &Quirks::XO_CHIP);
+
+        assert_eq!(lines(&assembly), [
+            "0200 SE V0, 0x00",
+            "0202 LD I, LONG 0x1234",
+            "0206 JP 0x208",
+            "loc_208:",
+            "0208 EXIT",
+        ]);
+    }
+
+    #[test]
+    fn tracing_labels_jump_tables() {
+        // Only the table's first entry is known to be code, since the rest depend on V0
+        let assembly = disassemble(&rom(&[0x6002, 0xB206, 0x1200, 0x1200]), 0x200, true, &Quirks::default());
+
+        assert_eq!(lines(&assembly), [
+            "loc_200:",
+            "0200 LD V0, 0x02",
+            "0202 JP V0, 0x206",
+            "0204 db 0x12  ; ...#..#.",
+            "0205 db 0x00  ; ........",
+            "table_206:",
+            "0206 JP 0x200",
+        ]);
+    }
+}
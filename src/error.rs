@@ -0,0 +1,97 @@
+use std::{fmt, io};
+
+/// Everything that can go wrong while loading or running a program.
+///
+/// Addresses are the value of the program counter when the fault happened, so the offending
+/// instruction can be found in the ROM.
+#[derive(Debug)]
+pub enum Chip8Error {
+    /// An opcode the platform doesn't have.
+    UnknownOpcode {
+        /// The opcode.
+        opcode: u16,
+        /// Where it was fetched from.
+        pc: usize
+    },
+    /// A call with all 16 stack entries in use.
+    StackOverflow {
+        /// The address of the call.
+        pc: usize
+    },
+    /// A return with an empty stack.
+    StackUnderflow {
+        /// The address of the return.
+        pc: usize
+    },
+    /// The program counter left the platform's memory.
+    PcOutOfRange {
+        /// The address it would have fetched from.
+        pc: usize
+    },
+    /// An instruction read or wrote past the end of the platform's memory.
+    MemoryOutOfRange {
+        /// The first address out of range.
+        address: usize,
+        /// The address of the instruction.
+        pc: usize
+    },
+    /// A rom that doesn't fit in memory where it's loaded.
+    RomTooLarge {
+        /// The rom's size in bytes.
+        size: usize,
+        /// The most bytes that fit.
+        max: usize
+    },
+    /// The address is below 0x200, where the interpreter and fonts live, or past the end of memory.
+    InvalidLoadAddress {
+        /// The address the rom was to be loaded at.
+        address: usize
+    },
+    /// A save state that is cut short or holds values the processor can't have.
+    InvalidSnapshot(String),
+    /// A save state from a newer or retired format.
+    UnsupportedSnapshotVersion {
+        /// The version in the save state's header.
+        version: u16
+    },
+    /// Reading a rom or save state failed.
+    Io(io::Error)
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { opcode, pc } => write!(f, "unknown opcode {:04x} at {:#05x}", opcode, pc),
+            Chip8Error::StackOverflow { pc } => write!(f, "stack overflow at {:#05x}", pc),
+            Chip8Error::StackUnderflow { pc } => write!(f, "stack underflow at {:#05x}", pc),
+            Chip8Error::PcOutOfRange { pc } => write!(f, "program counter out of range: {:#05x}", pc),
+            Chip8Error::MemoryOutOfRange { address, pc } => {
+                write!(f, "memory access out of range: {:#05x} at {:#05x}", address, pc)
+            },
+            Chip8Error::RomTooLarge { size, max } => write!(f, "rom is {} bytes, but at most {} bytes fit in memory", size, max),
+            Chip8Error::InvalidLoadAddress { address } => {
+                write!(f, "can't load a rom at {:#05x}, it has to be between 0x200 and the end of memory", address)
+            },
+            Chip8Error::InvalidSnapshot(reason) => write!(f, "invalid save state: {}", reason),
+            Chip8Error::UnsupportedSnapshotVersion { version } => {
+                write!(f, "save state format version {} is not supported (expected {})", version, crate::snapshot::VERSION)
+            },
+            Chip8Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(err: io::Error) -> Self {
+        Chip8Error::Io(err)
+    }
+}
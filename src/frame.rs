@@ -0,0 +1,371 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use crate::debugger::Debugger;
+use crate::error::Chip8Error;
+use crate::processor::Processor;
+use crate::timing::{self, VIP_INTERPRETER_CYCLES};
+
+/// Frames per second, which is also the rate the timers count down at.
+pub const FRAME_RATE: u32 = 60;
+/// How long a frame lasts in real time at normal speed.
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / FRAME_RATE as u64);
+/// The most frames [`FrameClock`] runs at once to catch up after falling behind. Anything
+/// beyond that is dropped, so a slow machine runs the game slower instead of falling further behind.
+pub const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+/// Instructions per second when neither the command line nor the rom's config says otherwise.
+pub const DEFAULT_SPEED: u32 = 600;
+/// The range `--speed` and the speed hotkeys are kept in.
+pub const SPEED_RANGE: RangeInclusive<u32> = 1..=1_000_000;
+
+/// A speed setting, as given on the command line, in a rom's config or in a movie.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuSpeed {
+    /// Instructions per second, all costing the same.
+    Hz(u32),
+    /// Instructions cost what they did on the COSMAC VIP, see [`crate::timing`].
+    Vip
+}
+
+impl CpuSpeed {
+    /// Parses `vip`, or a number of instructions per second within [`SPEED_RANGE`].
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "vip" => Some(CpuSpeed::Vip),
+            hz => hz.parse::<u32>().ok().filter(|hz| SPEED_RANGE.contains(hz)).map(CpuSpeed::Hz),
+        }
+    }
+}
+
+impl Default for CpuSpeed {
+    fn default() -> Self {
+        CpuSpeed::Hz(DEFAULT_SPEED)
+    }
+}
+
+impl fmt::Display for CpuSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuSpeed::Hz(hz) => write!(f, "{}", hz),
+            CpuSpeed::Vip => write!(f, "vip"),
+        }
+    }
+}
+
+// What a frame's budget is counted in
+#[derive(Clone, Copy, PartialEq)]
+enum Unit {
+    Instructions,
+    VipCycles
+}
+
+// VIP machine cycles per second at the original speed
+const VIP_HZ: u32 = VIP_INTERPRETER_CYCLES * FRAME_RATE;
+
+/// CPU speed, handed out a frame at a time: a number of instructions, or with VIP timing a
+/// number of machine cycles. Speeds that aren't a multiple of 60 get a varying budget per frame,
+/// carrying the remainder over so the average stays exact.
+pub struct Speed {
+    unit: Unit,
+    hz: u32, // Instructions or machine cycles per second
+    remainder: u32, // Sixtieths carried over from earlier frames
+    overspent: u32 // Cycles the last instruction of a frame ran past its budget, taken from the next
+}
+
+impl Speed {
+    /// Starts counting at `speed`, with nothing carried over.
+    pub fn new(speed: CpuSpeed) -> Self {
+        let (unit, hz) = match speed {
+            CpuSpeed::Hz(hz) => (Unit::Instructions, hz),
+            CpuSpeed::Vip => (Unit::VipCycles, VIP_HZ),
+        };
+
+        let mut speed = Speed { unit, hz, remainder: 0, overspent: 0 };
+        speed.set_hz(hz);
+        speed
+    }
+
+    /// Speeds up by a quarter.
+    pub fn faster(&mut self) {
+        self.set_hz(self.hz + self.hz.div_ceil(4));
+    }
+
+    /// Slows down by a fifth, roughly undoing [`Speed::faster`].
+    pub fn slower(&mut self) {
+        self.set_hz(self.hz - self.hz.div_ceil(5));
+    }
+
+    fn set_hz(&mut self, hz: u32) {
+        // VIP timing can go from 1% to 100 times the original speed
+        let (min, max) = match self.unit {
+            Unit::Instructions => (*SPEED_RANGE.start(), *SPEED_RANGE.end()),
+            Unit::VipCycles => (VIP_HZ / 100, VIP_HZ * 100),
+        };
+        self.hz = hz.clamp(min, max);
+    }
+
+    // The budget for the next frame
+    fn next_frame(&mut self) -> u32 {
+        let total = self.hz + self.remainder;
+        self.remainder = total % FRAME_RATE;
+        (total / FRAME_RATE).saturating_sub(std::mem::take(&mut self.overspent))
+    }
+
+    // What running the instruction at the program counter costs
+    fn cost(&self, processor: &Processor) -> u32 {
+        match self.unit {
+            Unit::Instructions => 1,
+            Unit::VipCycles => timing::vip_cycles(processor),
+        }
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::new(CpuSpeed::default())
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.unit {
+            Unit::Instructions => write!(f, "{} instructions per second", self.hz),
+            Unit::VipCycles => write!(f, "{}% of COSMAC VIP speed", self.hz as u64 * 100 / VIP_HZ as u64),
+        }
+    }
+}
+
+/// What happened in a frame.
+pub struct FrameResult {
+    /// Whether anything was drawn, so the screen needs redrawing.
+    pub vram_updated: bool,
+    /// How many instructions ran.
+    pub instructions: u32
+}
+
+/// Runs one 60Hz frame: the timers count down once, then the processor runs until it has used
+/// up the frame's budget, or until it waits for the display. With a debugger, instructions go
+/// through it and the frame ends early when it pauses.
+pub fn run_frame(processor: &mut Processor, mut debugger: Option<&mut Debugger>, speed: &mut Speed) -> Result<FrameResult, Chip8Error> {
+    processor.decrement_timers();
+
+    let budget = speed.next_frame();
+    let mut spent = 0;
+    let mut result = FrameResult { vram_updated: false, instructions: 0 };
+    while spent < budget && !processor.halted() && !processor.waiting_for_display() {
+        spent += speed.cost(processor);
+
+        let state = match debugger.as_deref_mut() {
+            Some(debugger) => debugger.tick(processor)?,
+            None => processor.tick()?,
+        };
+        result.vram_updated |= state.vram_updated;
+        result.instructions += 1;
+
+        if debugger.as_ref().is_some_and(|debugger| !debugger.is_running()) {
+            return Ok(result); // Paused partway, so nothing was overspent
+        }
+    }
+
+    speed.overspent = spent.saturating_sub(budget);
+    Ok(result)
+}
+
+/// Decides when the window frontend should run frames, using a fixed timestep: real time is
+/// added up as it passes and taken away a whole frame at a time, so frames that arrive late are
+/// made up for instead of lost.
+pub struct FrameClock {
+    last_update: Instant,
+    accumulator: Duration, // Emulated time that has passed but hasn't been run yet
+    rate: f64 // Emulated time per real time
+}
+
+impl FrameClock {
+    /// Starts the clock now, at normal speed.
+    pub fn new() -> Self {
+        FrameClock { last_update: Instant::now(), accumulator: Duration::ZERO, rate: 1.0 }
+    }
+
+    /// Runs faster (above 1) or slower (below 1) than real time.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate;
+    }
+
+    /// The number of frames to run now. After falling behind, it catches up by at most
+    /// [`MAX_CATCH_UP_FRAMES`] (times the rate, when running faster than real time).
+    pub fn frames_due(&mut self) -> u32 {
+        self.frames_due_at(Instant::now())
+    }
+
+    fn frames_due_at(&mut self, now: Instant) -> u32 {
+        self.accumulator += now.saturating_duration_since(self.last_update).mul_f64(self.rate);
+        self.last_update = now;
+
+        let cap = (MAX_CATCH_UP_FRAMES as f64 * self.rate.max(1.0)).ceil() as u32;
+        let frames = self.accumulator.as_nanos() / FRAME_DURATION.as_nanos();
+        if frames > cap as u128 {
+            self.accumulator = Duration::ZERO;
+            return cap;
+        }
+
+        self.accumulator -= FRAME_DURATION * frames as u32;
+        frames as u32
+    }
+
+    /// When the next frame is due, to sleep until then.
+    pub fn next_frame(&self) -> Instant {
+        self.last_update + FRAME_DURATION.saturating_sub(self.accumulator).div_f64(self.rate)
+    }
+
+    /// Forgets the time that has passed, e.g. while paused, so it isn't caught up on afterwards.
+    pub fn resync(&mut self) {
+        self.last_update = Instant::now();
+        self.accumulator = Duration::ZERO;
+    }
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        FrameClock::new()
+    }
+}
+
+/// Measures how many frames and instructions actually ran over the last second.
+pub struct Meter {
+    start: Instant,
+    frames: u32,
+    instructions: u64,
+    fps: f64,
+    ips: f64
+}
+
+impl Meter {
+    /// Starts measuring now.
+    pub fn new() -> Self {
+        Meter { start: Instant::now(), frames: 0, instructions: 0, fps: 0.0, ips: 0.0 }
+    }
+
+    /// Counts a frame that ran.
+    pub fn record(&mut self, result: &FrameResult) {
+        self.frames += 1;
+        self.instructions += result.instructions as u64;
+    }
+
+    /// Takes a new measurement once a second has passed. Returns true if it did.
+    pub fn update(&mut self) -> bool {
+        let elapsed = self.start.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return false;
+        }
+
+        self.fps = self.frames as f64 / elapsed.as_secs_f64();
+        self.ips = self.instructions as f64 / elapsed.as_secs_f64();
+        self.start = Instant::now();
+        self.frames = 0;
+        self.instructions = 0;
+        true
+    }
+
+    /// Frames per second.
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    /// Instructions per second.
+    pub fn ips(&self) -> f64 {
+        self.ips
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Meter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_adds_up_over_a_second() {
+        for hz in [1, 59, 60, 500, 601, 1000, 12345] {
+            let mut speed = Speed::new(CpuSpeed::Hz(hz));
+            let instructions: u32 = (0..FRAME_RATE).map(|_| speed.next_frame()).sum();
+            assert_eq!(instructions, hz);
+        }
+    }
+
+    #[test]
+    fn speed_spreads_the_remainder_evenly() {
+        let mut speed = Speed::new(CpuSpeed::Hz(500)); // 8.33 instructions per frame
+        let frames: Vec<u32> = (0..6).map(|_| speed.next_frame()).collect();
+        assert_eq!(frames, [8, 8, 9, 8, 8, 9]);
+    }
+
+    #[test]
+    fn vip_timing_spends_cycles() {
+        let mut processor = Processor::new(crate::Quirks::COSMAC_VIP);
+        let mut speed = Speed::new(CpuSpeed::Vip);
+
+        // A jump to itself, so every instruction costs the same
+        let mut snapshot = processor.snapshot();
+        snapshot.ram[0x200..0x202].copy_from_slice(&[0x12, 0x00]);
+        processor.restore(&snapshot);
+
+        let cost = timing::vip_cycles(&processor);
+        let result = run_frame(&mut processor, None, &mut speed).unwrap();
+        assert_eq!(result.instructions, VIP_INTERPRETER_CYCLES.div_ceil(cost));
+        assert_eq!(speed.overspent, result.instructions * cost - VIP_INTERPRETER_CYCLES);
+    }
+
+    #[test]
+    fn clock_catches_up_on_late_frames() {
+        let mut clock = FrameClock::new();
+        let start = clock.last_update;
+
+        assert_eq!(clock.frames_due_at(start + FRAME_DURATION / 2), 0);
+        assert_eq!(clock.frames_due_at(start + FRAME_DURATION * 3), 3); // Late, but nothing is lost
+        assert_eq!(clock.next_frame(), start + FRAME_DURATION * 4);
+    }
+
+    #[test]
+    fn clock_drops_time_beyond_the_catch_up_cap() {
+        let mut clock = FrameClock::new();
+        let start = clock.last_update;
+
+        assert_eq!(clock.frames_due_at(start + Duration::from_secs(1)), MAX_CATCH_UP_FRAMES);
+        assert_eq!(clock.frames_due_at(start + Duration::from_secs(1)), 0);
+    }
+
+    #[test]
+    fn clock_runs_at_the_rate() {
+        let mut clock = FrameClock::new();
+        let start = clock.last_update;
+
+        clock.set_rate(4.0);
+        assert_eq!(clock.frames_due_at(start + FRAME_DURATION), 4);
+
+        clock.set_rate(0.25);
+        assert_eq!(clock.frames_due_at(start + FRAME_DURATION * 4), 0);
+        assert_eq!(clock.frames_due_at(start + FRAME_DURATION * 5), 1);
+    }
+
+    #[test]
+    fn faster_and_slower_stay_in_range() {
+        let mut speed = Speed::new(CpuSpeed::Hz(600));
+        speed.faster();
+        assert_eq!(speed.hz, 750);
+        speed.slower();
+        assert_eq!(speed.hz, 600);
+
+        let mut speed = Speed::new(CpuSpeed::Hz(1));
+        speed.slower();
+        assert_eq!(speed.hz, 1);
+        let mut speed = Speed::new(CpuSpeed::Hz(*SPEED_RANGE.end()));
+        speed.faster();
+        assert_eq!(speed.hz, *SPEED_RANGE.end());
+    }
+}
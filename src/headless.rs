@@ -0,0 +1,240 @@
+use std::{fmt::Write as _, fs, fs::File, io::BufWriter};
+
+use chip8::database;
+use chip8::frame::{self, Speed};
+use chip8::movie::Input;
+use chip8::palette::Palette;
+use chip8::processor::{Processor, State};
+use chip8::rng::Rng;
+
+use crate::cli::{self, Failure, HeadlessOptions, TestOptions};
+use crate::rom_config::RomConfig;
+
+/// When a headless run stops before its frame limit.
+#[derive(Clone, Copy)]
+pub enum Until {
+    Exit, // The program ran the SUPER-CHIP exit instruction
+    Idle, // The program is stuck in a jump to itself, which is how most test ROMs end
+    Pc(usize) // The program counter reached an address
+}
+
+impl Until {
+    pub fn parse(condition: &str) -> Option<Self> {
+        match condition {
+            "exit" => Some(Until::Exit),
+            "idle" => Some(Until::Idle),
+            _ => {
+                let address = condition.strip_prefix("pc=")?;
+                usize::from_str_radix(address.trim_start_matches("0x"), 16).ok().map(Until::Pc)
+            },
+        }
+    }
+
+    fn reached(&self, processor: &Processor) -> bool {
+        match self {
+            Until::Exit => processor.halted(),
+            Until::Idle => {
+                let ram = processor.ram();
+                let pc = processor.pc();
+                let opcode = ram.get(pc..pc + 2).map_or(0, |bytes| (bytes[0] as usize) << 8 | bytes[1] as usize);
+                opcode == (0x1000 | pc) // 1NNN with NNN = its own address
+            },
+            Until::Pc(address) => processor.pc() == *address,
+        }
+    }
+}
+
+// The machine at the end of a headless run
+struct Finished {
+    processor: Processor,
+    palette: Palette,
+    frames: u64
+}
+
+/// Runs a rom without a window, as fast as possible, then writes out the screen and registers.
+pub fn run(options: &HeadlessOptions) -> Result<(), Failure> {
+    let Finished { processor, palette, frames } = emulate(options)?;
+    let mut result = Ok(());
+
+    if let Some(path) = &options.screenshot {
+        let written = if path.ends_with(".png") {
+            write_png(path, &processor.state(), &palette)
+        } else {
+            write_output(path, &processor.state().ascii_art())
+        };
+        if let Err(err) = written {
+            eprintln!("[-] Could not write {}: {}", path, err);
+            result = Err(Failure::Error);
+        }
+    }
+
+    if let Some(path) = &options.registers
+        && let Err(err) = write_output(path, &registers_json(&processor, frames))
+    {
+        eprintln!("[-] Could not write {}: {}", path, err);
+        result = Err(Failure::Error);
+    }
+
+    result
+}
+
+/// Runs a rom headless and compares the screen it ends on with the expected one, or writes it
+/// as the expected screen when blessing.
+pub fn test(options: &TestOptions) -> Result<(), Failure> {
+    let screen = emulate(&options.run)?.processor.state().ascii_art();
+
+    if options.bless {
+        return match fs::write(&options.expect, &screen) {
+            Ok(()) => {
+                println!("[+] Wrote the screen to {}", options.expect);
+                Ok(())
+            },
+            Err(err) => {
+                eprintln!("[-] Could not write {}: {}", options.expect, err);
+                Err(Failure::Error)
+            }
+        };
+    }
+
+    let expected = fs::read_to_string(&options.expect).map_err(|err| {
+        eprintln!("[-] Could not read {}: {} (pass --bless to create it)", options.expect, err);
+        Failure::Error
+    })?;
+
+    if screen == expected {
+        println!("[+] Passed: the screen matches {}", options.expect);
+        Ok(())
+    } else {
+        println!("[-] Failed: the screen doesn't match {}:\n{}", options.expect, screen);
+        Err(Failure::Error)
+    }
+}
+
+fn emulate(options: &HeadlessOptions) -> Result<Finished, Failure> {
+    let inputs = match &options.keys {
+        Some(path) => load_key_script(path).map_err(|err| {
+            eprintln!("[-] Could not load key script: {}", err);
+            Failure::Error
+        })?,
+        None => Vec::new(),
+    };
+
+    let rom_config = RomConfig::load(&options.rom).map_err(|err| {
+        eprintln!("[-] Could not load rom config: {}", err);
+        Failure::Usage
+    })?;
+
+    let database = cli::load_database(options.database.as_deref()).map_err(|err| {
+        eprintln!("[-] Could not load rom database: {}", err);
+        Failure::Usage
+    })?;
+
+    // The rom is read first, since its hash picks the settings the database recommends for it
+    let rom = cli::read_rom(&options.rom).map_err(|err| {
+        eprintln!("[-] Could not read {}: {}", options.rom, err);
+        Failure::Load
+    })?;
+    let rom_info = database.get(&database::hash(&rom));
+    if let Some(info) = rom_info {
+        eprintln!("[+] Found {} in the rom database, using its recommended settings", info.title);
+    }
+
+    let mut processor = Processor::new(options.platform.or(rom_config.quirks).or(rom_info.and_then(|info| info.quirks)).unwrap_or(options.defaults.quirks));
+    processor.set_rng(Rng::new(options.random, options.seed));
+    match processor.load_bytes(&rom, options.origin) {
+        Ok(size) => eprintln!("[+] Loaded {} bytes at {:#05x}", size, options.origin),
+        Err(err) => {
+            eprintln!("[-] Could not load {}: {}", options.rom, err);
+            return Err(Failure::Load);
+        }
+    }
+    let mut speed = Speed::new(options.speed.or(rom_config.speed).or(rom_info.and_then(|info| info.speed)).unwrap_or(options.defaults.speed));
+    let palette = options.palette.or(rom_config.palette).or(rom_info.and_then(|info| info.palette)).unwrap_or(options.defaults.palette);
+
+    let mut frames = 0;
+    let mut inputs = inputs.iter().peekable();
+    while frames < options.frames {
+        while let Some(input) = inputs.next_if(|input| input.frame <= frames) {
+            processor.set_key(input.key, input.pressed);
+        }
+
+        if let Err(err) = frame::run_frame(&mut processor, None, &mut speed) {
+            eprintln!("[-] Emulation fault after {} frames: {}", frames, err);
+            return Err(Failure::Fault);
+        }
+        frames += 1;
+
+        if options.until.is_some_and(|until| until.reached(&processor)) {
+            break;
+        }
+    }
+
+    eprintln!("[+] Ran {} frames", frames);
+    Ok(Finished { processor, palette, frames })
+}
+
+// A key script has one change per line: the frame it happens at, the hex key and down or up,
+// e.g. `120 5 down`. Lines starting with # are comments.
+fn load_key_script(path: &String) -> Result<Vec<Input>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut inputs = Vec::new();
+
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let input = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [frame, key, state] => Input::parse(frame, key, state),
+            _ => None,
+        };
+        inputs.push(input.ok_or(format!("{}:{}: expected `<frame> <hex key> down|up`", path, line_number + 1))?);
+    }
+
+    inputs.sort_by_key(|input| input.frame); // Keeps lines for the same frame in order
+    Ok(inputs)
+}
+
+// Writes to a file, or to stdout if the path is -
+fn write_output(path: &str, contents: &str) -> Result<(), String> {
+    if path == "-" {
+        print!("{}", contents);
+        return Ok(());
+    }
+
+    fs::write(path, contents).map_err(|e| e.to_string())
+}
+
+fn write_png(path: &str, state: &State, palette: &Palette) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), state.width as u32, state.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let data: Vec<u8> = state.vram[..state.width * state.height].iter()
+        .flat_map(|pixel| palette.color(*pixel))
+        .collect();
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&data).map_err(|e| e.to_string())
+}
+
+fn registers_json(processor: &Processor, frames: u64) -> String {
+    let list = |values: Vec<String>| values.join(", ");
+
+    let mut json = String::from("{\n");
+    let _ = writeln!(json, "  \"frames\": {},", frames);
+    let _ = writeln!(json, "  \"pc\": {},", processor.pc());
+    let _ = writeln!(json, "  \"i\": {},", processor.i());
+    let _ = writeln!(json, "  \"v\": [{}],", list(processor.v().iter().map(u8::to_string).collect()));
+    let _ = writeln!(json, "  \"sp\": {},", processor.sp());
+    let _ = writeln!(json, "  \"stack\": [{}],", list(processor.stack().iter().map(usize::to_string).collect()));
+    let _ = writeln!(json, "  \"delay_timer\": {},", processor.delay_timer);
+    let _ = writeln!(json, "  \"sound_timer\": {},", processor.sound_timer);
+    let _ = writeln!(json, "  \"halted\": {}", processor.halted());
+    json.push_str("}\n");
+
+    json
+}
@@ -0,0 +1,150 @@
+use std::{collections::HashMap, fs};
+
+use chip8::database::KeyLayout;
+
+use winit::keyboard::KeyCode;
+
+use crate::config::KEY_NAMES;
+
+// The COSMAC VIP keypad is laid out like this:
+//
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+//
+// and is usually mapped onto the left side of a QWERTY keyboard.
+const DEFAULT_LAYOUT: [(KeyCode, u8); 16] = [
+    (KeyCode::Digit1, 0x1), (KeyCode::Digit2, 0x2), (KeyCode::Digit3, 0x3), (KeyCode::Digit4, 0xC),
+    (KeyCode::KeyQ, 0x4),   (KeyCode::KeyW, 0x5),   (KeyCode::KeyE, 0x6),   (KeyCode::KeyR, 0xD),
+    (KeyCode::KeyA, 0x7),   (KeyCode::KeyS, 0x8),   (KeyCode::KeyD, 0x9),   (KeyCode::KeyF, 0xE),
+    (KeyCode::KeyZ, 0xA),   (KeyCode::KeyX, 0x0),   (KeyCode::KeyC, 0xB),   (KeyCode::KeyV, 0xF),
+];
+
+// Keys that can be used in a mapping file, in the same order as their names in `KEY_NAMES`
+const BINDABLE_KEYS: [KeyCode; 66] = [
+    KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+    KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+    KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF,
+    KeyCode::KeyG, KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL,
+    KeyCode::KeyM, KeyCode::KeyN, KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR,
+    KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU, KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX,
+    KeyCode::KeyY, KeyCode::KeyZ,
+    KeyCode::Numpad0, KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::Numpad4,
+    KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9,
+    KeyCode::NumpadAdd, KeyCode::NumpadSubtract, KeyCode::NumpadMultiply, KeyCode::NumpadDivide,
+    KeyCode::NumpadDecimal, KeyCode::NumpadEnter,
+    KeyCode::ArrowUp, KeyCode::ArrowDown, KeyCode::ArrowLeft, KeyCode::ArrowRight,
+    KeyCode::Space, KeyCode::Enter, KeyCode::Tab, KeyCode::Backspace,
+    KeyCode::Comma, KeyCode::Period, KeyCode::Slash, KeyCode::Semicolon,
+    KeyCode::ShiftLeft, KeyCode::ShiftRight,
+];
+
+/// Translates physical keyboard keys into CHIP-8 hex keypad keys (0x0 - 0xF).
+pub struct Keymap {
+    bindings: HashMap<KeyCode, u8>
+}
+
+impl Keymap {
+    /// The default layout, with the arrow keys, Space and Enter also bound to the hex keys a rom
+    /// uses for its directions and its A and B buttons.
+    pub fn with_layout(layout: &KeyLayout) -> Self {
+        let mut keymap = Keymap::default();
+        let keys = [
+            (KeyCode::ArrowUp, layout.up),
+            (KeyCode::ArrowDown, layout.down),
+            (KeyCode::ArrowLeft, layout.left),
+            (KeyCode::ArrowRight, layout.right),
+            (KeyCode::Space, layout.a),
+            (KeyCode::Enter, layout.b),
+        ];
+        for (code, key) in keys {
+            if let Some(key) = key {
+                keymap.bindings.insert(code, key);
+            }
+        }
+
+        keymap
+    }
+
+    /// Loads a mapping file on top of another keymap, usually the default 1234/QWER/ASDF/ZXCV
+    /// layout.
+    ///
+    /// Every non-empty line has the form `<hex key> = <key name>`, e.g. `5 = ArrowUp`. A hex key
+    /// may be listed more than once to bind several keyboard keys to it. Any hex key that appears
+    /// in the file loses its existing bindings. Lines starting with `#` are ignored.
+    pub fn load(path: &String, mut keymap: Keymap) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+
+        let mut overridden = [false; 16];
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (hex, name) = line.split_once('=')
+                .ok_or(format!("{}:{}: expected `<hex key> = <key name>`", path, line_number + 1))?;
+
+            let hex = u8::from_str_radix(hex.trim(), 16)
+                .ok()
+                .filter(|key| *key < 16)
+                .ok_or(format!("{}:{}: `{}` is not a hex key (0-F)", path, line_number + 1, hex.trim()))?;
+
+            let code = parse_key_code(name.trim())
+                .ok_or(format!("{}:{}: unknown key name `{}`", path, line_number + 1, name.trim()))?;
+
+            keymap.bind(hex, code, &mut overridden);
+        }
+
+        Ok(keymap)
+    }
+
+    /// Binds keyboard keys, given by name, to hex keys, e.g. from the config file. As in a
+    /// mapping file, any hex key listed loses its existing bindings.
+    pub fn with_bindings(mut self, bindings: &[(u8, String)]) -> Result<Self, String> {
+        let mut overridden = [false; 16];
+        for (hex, name) in bindings {
+            let code = parse_key_code(name).ok_or(format!("unknown key name `{}`", name))?;
+            self.bind(*hex, code, &mut overridden);
+        }
+
+        Ok(self)
+    }
+
+    // The first binding for a hex key replaces the ones it had before
+    fn bind(&mut self, hex: u8, code: KeyCode, overridden: &mut [bool; 16]) {
+        if !overridden[hex as usize] {
+            self.bindings.retain(|_, key| *key != hex);
+            overridden[hex as usize] = true;
+        }
+        self.bindings.insert(code, hex);
+    }
+
+    pub fn get(&self, code: KeyCode) -> Option<u8> {
+        self.bindings.get(&code).copied()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap { bindings: HashMap::from(DEFAULT_LAYOUT) }
+    }
+}
+
+fn parse_key_code(name: &str) -> Option<KeyCode> {
+    KEY_NAMES.iter().position(|key| *key == name).map(|index| BINDABLE_KEYS[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_names_match_winit() {
+        for (name, code) in KEY_NAMES.iter().zip(BINDABLE_KEYS) {
+            assert_eq!(*name, format!("{:?}", code));
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! A CHIP-8 emulator core with the SUPER-CHIP 1.1 and XO-CHIP extensions.
+//!
+//! [`Processor`] runs programs: load a rom, call [`Processor::tick`] for each instruction and
+//! [`Processor::decrement_timers`] 60 times a second (or let [`frame::run_frame`] do both), and
+//! draw [`Processor::state`]. Nothing here depends on a window or graphics stack, so the core
+//! can be used by tools, tests and other frontends.
+//!
+//! ```no_run
+//! use chip8::{Processor, Quirks};
+//!
+//! use chip8::frame::{self, CpuSpeed, Speed};
+//!
+//! let mut processor = Processor::new(Quirks::COSMAC_VIP);
+//! processor.load("pong.ch8")?;
+//!
+//! let mut speed = Speed::new(CpuSpeed::Hz(700)); // Instructions per second
+//! for _ in 0..60 {
+//!     frame::run_frame(&mut processor, None, &mut speed)?;
+//! }
+//! let screen = processor.state();
+//! # Ok::<(), chip8::Chip8Error>(())
+//! ```
+
+#![warn(missing_docs)]
+
+/// The CPU, memory and screen.
+pub mod processor;
+/// The built-in fonts.
+pub mod font;
+/// Settings for behaviors that differ between platforms.
+pub mod quirks;
+/// Emulation and loading errors.
+pub mod error;
+/// The random number generator behind CXNN.
+pub mod rng;
+/// The beeper and XO-CHIP audio patterns.
+pub mod audio;
+/// Colors for the XO-CHIP plane combinations.
+pub mod palette;
+/// Save states.
+pub mod snapshot;
+/// A history of recent frames to step back through.
+pub mod rewind;
+/// Recording and replaying key presses.
+pub mod movie;
+/// The 60Hz frame loop shared by the frontends.
+pub mod frame;
+/// How long instructions took on the COSMAC VIP.
+pub mod timing;
+/// Breakpoints, watchpoints and stepping.
+pub mod debugger;
+/// Turning roms into assembly.
+pub mod disasm;
+/// Turning assembly into roms.
+pub mod asm;
+/// Label names for addresses.
+pub mod symbols;
+/// Recommended settings for known roms.
+pub mod database;
+
+pub use error::Chip8Error;
+pub use processor::{Processor, State};
+pub use quirks::{MemoryIncrement, Quirks};
@@ -1,117 +1,135 @@
-mod processor;
-mod font;
-
-use std::{env, time::Duration};
-
-use processor::Processor;
-
-use pixels::{Error, Pixels, SurfaceTexture};
-use winit::dpi::LogicalSize;
-use winit::event::{Event, WindowEvent};
-use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::WindowBuilder;
+mod cli;
+mod config;
+mod headless;
+#[cfg(feature = "gui")]
+mod keypad;
+mod rom_config;
+#[cfg(feature = "gui")]
+mod window;
+
+use std::{env, fs, path::Path, process::ExitCode};
+
+use chip8::database;
+use chip8::{asm, disasm};
+
+use cli::{AsmOptions, Command, DisasmOptions, Failure, InfoOptions, Options};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match Command::parse(&args) {
+        Ok(Command::Run(options)) => run(options),
+        Ok(Command::Disasm(options)) => disassemble(&options),
+        Ok(Command::Asm(options)) => assemble(&options),
+        Ok(Command::Info(options)) => info(&options),
+        Ok(Command::Test(options)) => headless::test(&options),
+        Ok(Command::Headless(options)) => headless::run(&options),
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        },
+        Ok(Command::Version) => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            Ok(())
+        },
+        Err(err) => {
+            eprintln!("[-] {}", err);
+            eprintln!("Run with --help to see the usage.");
+            Err(Failure::Usage)
+        }
+    };
 
-use crate::processor::State;
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => ExitCode::from(failure.exit_code()),
+    }
+}
 
-fn print_usage() {
-    println!("Usage: Chip8-Emulator [pathToGame]");
+#[cfg(feature = "gui")]
+fn run(options: Options) -> Result<(), Failure> {
+    window::run(options)
 }
 
-fn main() {
-    println!("[+] Initializing emulator...");
+#[cfg(not(feature = "gui"))]
+fn run(_options: Options) -> Result<(), Failure> {
+    eprintln!("[-] This build has no window. Rebuild with the gui feature, or use --headless.");
+    Err(Failure::Usage)
+}
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        print_usage();
-        return;
+fn disassemble(options: &DisasmOptions) -> Result<(), Failure> {
+    let rom = fs::read(&options.rom).map_err(|err| {
+        eprintln!("[-] Could not read {}: {}", options.rom, err);
+        Failure::Load
+    })?;
+
+    let assembly = disasm::disassemble(&rom, options.origin, options.recursive, &options.platform);
+
+    match &options.output {
+        Some(path) => fs::write(path, assembly).map_err(|err| {
+            eprintln!("[-] Could not write {}: {}", path, err);
+            Failure::Error
+        }),
+        None => {
+            print!("{}", assembly);
+            Ok(())
+        },
     }
+}
 
-    env_logger::init(); // WGPU will fail silently without this 
-    
-    let event_loop = EventLoop::new().unwrap();
-    event_loop.set_control_flow(ControlFlow::Poll);
-
-    let window = {
-        let size = LogicalSize::new(64, 42);
-        WindowBuilder::new()
-            .with_title("CHIP8")
-            .with_inner_size(size)
-            .with_min_inner_size(size)
-            .build(&event_loop)
-            .unwrap()
-    };
-
-    let mut pixels = {
-        let window_size = window.inner_size();
-        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(64, 32, surface_texture).unwrap()
-    };
+fn assemble(options: &AsmOptions) -> Result<(), Failure> {
+    let assembly = asm::assemble_file(Path::new(&options.source), options.target).map_err(|err| {
+        eprintln!("[-] {}", err);
+        Failure::Error
+    })?;
 
-    let mut processor = Processor::new();
-
-    println!("[+] Loading rom...");
-    processor.load(&args[1]);
-    
-    println!("[+] Starting emulation cycle...");
-
-    let mut last_frame = std::time::Instant::now();
-    let frame_duration = Duration::from_millis(16); // 16ms ~= 60Hz 
-    
-    let mut latest_vram = [false; 64 * 32];
-
-    let res = event_loop.run(|event, elwt| {
-        match event {
-            Event::WindowEvent {
-                event: WindowEvent::CloseRequested,
-                ..
-            } => {
-                println!("[+] Shutting down emulator...");
-                elwt.exit();
-            }
-            Event::WindowEvent {
-                event: WindowEvent::RedrawRequested,
-                ..
-            } => {
-                draw(pixels.frame_mut(), &latest_vram);
-                
-                if let Err(err) = pixels.render() {
-                    eprintln!("Render error: {}", err);
-                    elwt.exit();
-                    return;
-                }
-            },
-            Event::AboutToWait => {
-                let now = std::time::Instant::now();
-                if now.duration_since(last_frame) >= frame_duration {
-                    processor.decrement_timers();
-
-                    let mut state: State = State { vram: [false; 64 * 32], vram_updated: false };
-                    for _ in 1..11 {
-                        state = processor.tick();
-                    }
-
-                    last_frame = now;
-
-                    //if state.vram_updated {
-                        latest_vram.copy_from_slice(&state.vram);
-                        window.request_redraw();
-                    //}
-                }  
-            },
-            _ => {},
-        }
+    let write = |path: &String, contents: &[u8]| fs::write(path, contents).map_err(|err| {
+        eprintln!("[-] Could not write {}: {}", path, err);
+        Failure::Error
     });
-    let _ = res.map_err(|e| Error::UserDefined(Box::new(e)));
+    write(&options.output, &assembly.rom)?;
+    write(&options.symbols, assembly.symbols.to_file_contents().as_bytes())?;
+
+    println!("[+] Assembled {} bytes into {}", assembly.rom.len(), options.output);
+    Ok(())
 }
 
-fn draw(frame: &mut [u8], vram: &[bool; 64*32]) {
-    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-        let on = vram[i];
-        let rgba = if on {
-            [0xFF, 0xFF, 0xFF, 0xFF] // White
-        } else {
-            [0x00, 0x00, 0x00, 0xFF] // Black
-        };
-        pixel.copy_from_slice(&rgba);
+fn info(options: &InfoOptions) -> Result<(), Failure> {
+    let database = cli::load_database(options.database.as_deref()).map_err(|err| {
+        eprintln!("[-] Could not load rom database: {}", err);
+        Failure::Usage
+    })?;
+
+    let rom = cli::read_rom(&options.rom).map_err(|err| {
+        eprintln!("[-] Could not read {}: {}", options.rom, err);
+        Failure::Load
+    })?;
+    let hash = database::hash(&rom);
+
+    println!("File:      {}", options.rom);
+    println!("Size:      {} bytes", rom.len());
+    println!("SHA-1:     {}", database::format_hash(&hash));
+
+    let Some(info) = database.get(&hash) else {
+        println!("Database:  not found");
+        return Ok(());
+    };
+
+    println!("Database:  {}", info.title);
+    if let Some(quirks) = info.quirks {
+        println!("Platform:  {}", quirks.preset_name().unwrap_or("custom quirks"));
+    }
+    if let Some(speed) = info.speed {
+        println!("Speed:     {} instructions per second", speed);
+    }
+    let keys = [("up", info.keys.up), ("down", info.keys.down), ("left", info.keys.left), ("right", info.keys.right), ("a", info.keys.a), ("b", info.keys.b)];
+    let keys: Vec<String> = keys.iter()
+        .filter_map(|(name, key)| key.map(|key| format!("{} {:X}", name, key)))
+        .collect();
+    if !keys.is_empty() {
+        println!("Keys:      {}", keys.join(", "));
     }
+    if let Some(palette) = info.palette {
+        println!("Colors:    {}", palette);
+    }
+
+    Ok(())
 }
@@ -0,0 +1,361 @@
+use std::{fmt::Write, fs};
+
+use crate::frame::CpuSpeed;
+use crate::processor::{PROGRAM_START, Processor};
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::rng::RandomMode;
+
+const HEADER: &str = "chip8-movie 1";
+
+// Every setting a movie has to give, so it never falls back on a default it wasn't recorded with
+const SETTINGS: [&str; 12] = [
+    "seed", "random", "speed", "origin",
+    "quirk vf_reset", "quirk shift_uses_vy", "quirk memory_increment", "quirk jump_uses_vx",
+    "quirk clip_sprites", "quirk large_sprites", "quirk display_wait", "quirk memory_size",
+];
+
+/// A key press or release, applied at the start of a frame.
+#[derive(Clone, Copy)]
+pub struct Input {
+    /// The frame it's applied at, counting from 0.
+    pub frame: u64,
+    /// The hex key, 0 to F.
+    pub key: u8,
+    /// Whether the key went down, rather than up.
+    pub pressed: bool
+}
+
+impl Input {
+    /// Parses the words of a `<frame> <hex key> down|up` entry.
+    pub fn parse(frame: &str, key: &str, state: &str) -> Option<Self> {
+        Some(Input {
+            frame: frame.parse().ok()?,
+            key: u8::from_str_radix(key, 16).ok().filter(|key| *key < 16)?,
+            pressed: match state {
+                "down" => true,
+                "up" => false,
+                _ => return None,
+            }
+        })
+    }
+}
+
+/// Everything needed to play a run back exactly: the settings that affect emulation, every key
+/// change and a hash of the screen after each frame.
+///
+/// Movies are text files with one entry per line. Every setting and every quirk has to be given:
+/// ```text
+/// chip8-movie 1
+/// seed 1234
+/// random xorshift
+/// speed 600
+/// origin 512
+/// quirk vf_reset true
+/// ...
+/// key 120 5 down
+/// frame 0 8f2a0c5e1b7d3946
+/// ```
+pub struct Movie {
+    /// The random number generator's seed.
+    pub seed: u64,
+    /// The random number generator.
+    pub random: RandomMode,
+    /// The quirks the run used.
+    pub quirks: Quirks,
+    /// The speed the run used.
+    pub speed: CpuSpeed,
+    /// Where the rom was loaded, as given with `--origin`.
+    pub origin: usize,
+    inputs: Vec<Input>,
+    hashes: Vec<u64> // One per frame, in order
+}
+
+impl Movie {
+    /// Starts an empty movie with these settings.
+    pub fn new(seed: u64, random: RandomMode, quirks: Quirks, speed: CpuSpeed, origin: usize) -> Self {
+        Movie { seed, random, quirks, speed, origin, inputs: Vec::new(), hashes: Vec::new() }
+    }
+
+    /// Reads a movie file. Errors name the file and, for a bad entry, its line.
+    pub fn load(path: &String) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut lines = contents.lines().enumerate();
+
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err(format!("{}: not a movie file (expected `{}` on the first line)", path, HEADER));
+        }
+
+        let mut movie = Movie::new(0, RandomMode::Xorshift, Quirks::default(), CpuSpeed::default(), PROGRAM_START);
+        let mut given = Vec::new();
+        for (line_number, line) in lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            movie.parse_line(&words)
+                .ok_or(format!("{}:{}: invalid line `{}`", path, line_number + 1, line.trim()))?;
+
+            match words.as_slice() {
+                ["quirk", name, ..] => given.push(format!("quirk {}", name)),
+                [setting, ..] => given.push(setting.to_string()),
+                [] => {},
+            }
+        }
+
+        if let Some(missing) = SETTINGS.iter().find(|setting| !given.iter().any(|given| given == *setting)) {
+            return Err(format!("{}: missing `{}`", path, missing));
+        }
+
+        Ok(movie)
+    }
+
+    fn parse_line(&mut self, words: &[&str]) -> Option<()> {
+        match words {
+            [] => {},
+            ["seed", seed] => self.seed = seed.parse().ok()?,
+            ["random", mode] => self.random = RandomMode::parse(mode)?,
+            ["speed", speed] => self.speed = CpuSpeed::parse(speed)?,
+            ["origin", origin] => self.origin = origin.parse().ok()?,
+            ["quirk", "vf_reset", value] => self.quirks.vf_reset = value.parse().ok()?,
+            ["quirk", "shift_uses_vy", value] => self.quirks.shift_uses_vy = value.parse().ok()?,
+            ["quirk", "memory_increment", value] => self.quirks.memory_increment = MemoryIncrement::parse(value)?,
+            ["quirk", "jump_uses_vx", value] => self.quirks.jump_uses_vx = value.parse().ok()?,
+            ["quirk", "clip_sprites", value] => self.quirks.clip_sprites = value.parse().ok()?,
+            ["quirk", "large_sprites", value] => self.quirks.large_sprites = value.parse().ok()?,
+            ["quirk", "display_wait", value] => self.quirks.display_wait = value.parse().ok()?,
+            ["quirk", "memory_size", value] => self.quirks.memory_size = value.parse().ok()?,
+            ["key", frame, key, state] => self.inputs.push(Input::parse(frame, key, state)?),
+            ["frame", frame, hash] => {
+                // Frames are written in order, so the number is only there for people reading the file
+                if frame.parse::<usize>().ok()? != self.hashes.len() {
+                    return None;
+                }
+                self.hashes.push(u64::from_str_radix(hash, 16).ok()?);
+            },
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    /// Writes the movie to a file.
+    pub fn save(&self, path: &String) -> Result<(), String> {
+        let mut output = String::new();
+        let _ = writeln!(output, "{}", HEADER);
+        let _ = writeln!(output, "seed {}", self.seed);
+        let _ = writeln!(output, "random {}", self.random.name());
+        let _ = writeln!(output, "speed {}", self.speed);
+        let _ = writeln!(output, "origin {}", self.origin);
+        let _ = writeln!(output, "quirk vf_reset {}", self.quirks.vf_reset);
+        let _ = writeln!(output, "quirk shift_uses_vy {}", self.quirks.shift_uses_vy);
+        let _ = writeln!(output, "quirk memory_increment {}", self.quirks.memory_increment.name());
+        let _ = writeln!(output, "quirk jump_uses_vx {}", self.quirks.jump_uses_vx);
+        let _ = writeln!(output, "quirk clip_sprites {}", self.quirks.clip_sprites);
+        let _ = writeln!(output, "quirk large_sprites {}", self.quirks.large_sprites);
+        let _ = writeln!(output, "quirk display_wait {}", self.quirks.display_wait);
+        let _ = writeln!(output, "quirk memory_size {}", self.quirks.memory_size);
+        for input in &self.inputs {
+            let _ = writeln!(output, "key {} {:x} {}", input.frame, input.key, if input.pressed { "down" } else { "up" });
+        }
+        for (frame, hash) in self.hashes.iter().enumerate() {
+            let _ = writeln!(output, "frame {} {:016x}", frame, hash);
+        }
+
+        fs::write(path, output).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+/// Records key changes and screen hashes as the emulator runs.
+pub struct Recorder {
+    movie: Movie,
+    keys: [bool; 16],
+    frame: u64
+}
+
+impl Recorder {
+    /// Starts recording into `movie`, which is usually empty.
+    pub fn new(movie: Movie) -> Self {
+        Recorder { movie, keys: [false; 16], frame: 0 }
+    }
+
+    /// Records a key change. It takes effect at the start of the next frame.
+    pub fn key(&mut self, key: u8, pressed: bool) {
+        let Some(state) = self.keys.get_mut(key as usize) else { return };
+        if *state != pressed { // Ignore key repeats
+            *state = pressed;
+            self.movie.inputs.push(Input { frame: self.frame, key, pressed });
+        }
+    }
+
+    /// Records the screen at the end of a frame.
+    pub fn end_frame(&mut self, processor: &Processor) {
+        self.movie.hashes.push(hash_vram(processor));
+        self.frame += 1;
+    }
+
+    /// The movie recorded so far.
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+/// Feeds a movie's key changes back into the processor and checks each frame against the
+/// recorded screen hashes.
+pub struct Player {
+    movie: Movie,
+    frame: u64,
+    next_input: usize,
+    diverged: bool
+}
+
+impl Player {
+    /// Starts playing `movie` back from its first frame.
+    pub fn new(movie: Movie) -> Self {
+        Player { movie, frame: 0, next_input: 0, diverged: false }
+    }
+
+    /// Applies the key changes recorded for this frame.
+    pub fn start_frame(&mut self, processor: &mut Processor) {
+        while let Some(input) = self.movie.inputs.get(self.next_input).filter(|input| input.frame <= self.frame) {
+            processor.set_key(input.key, input.pressed);
+            self.next_input += 1;
+        }
+    }
+
+    /// Checks the screen against the recording. Returns the frame number the first time the
+    /// replay diverges.
+    pub fn end_frame(&mut self, processor: &Processor) -> Option<u64> {
+        let frame = self.frame;
+        self.frame += 1;
+
+        let expected = self.movie.hashes.get(frame as usize)?;
+        if self.diverged || *expected == hash_vram(processor) {
+            return None;
+        }
+
+        self.diverged = true;
+        Some(frame)
+    }
+
+    /// True once every recorded frame has been played.
+    pub fn finished(&self) -> bool {
+        self.frame as usize >= self.movie.hashes.len()
+    }
+}
+
+/// A hash of what's on screen. This is FNV-1a, which is stable across builds and platforms,
+/// unlike the standard library's hasher.
+pub fn hash_vram(processor: &Processor) -> u64 {
+    let state = processor.state();
+    let pixels = &state.vram[..state.width * state.height];
+
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in [state.width as u8, state.height as u8].iter().chain(pixels) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // Waits for a key and draws its digit, forever
+    const ROM: [u8; 8] = [0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x00];
+    const FRAMES: u64 = 10;
+
+    fn quirks() -> Quirks {
+        Quirks { display_wait: false, ..Quirks::COSMAC_VIP }
+    }
+
+    fn processor() -> Processor {
+        let mut processor = Processor::new(quirks());
+        processor.load_bytes(&ROM, 0x200).unwrap();
+        processor
+    }
+
+    fn run_frame(processor: &mut Processor) {
+        for _ in 0..10 {
+            processor.tick().unwrap();
+        }
+    }
+
+    // Presses 5 in frame 1 and 9 in frame 4, releasing each a frame later
+    fn record() -> Movie {
+        let mut processor = processor();
+        let mut recorder = Recorder::new(Movie::new(7, RandomMode::Vip, quirks(), CpuSpeed::Hz(600), 0x200));
+
+        for frame in 0..FRAMES {
+            for (key, pressed) in [(5, frame == 1), (9, frame == 4)] {
+                recorder.key(key, pressed);
+                processor.set_key(key, pressed);
+            }
+            run_frame(&mut processor);
+            recorder.end_frame(&processor);
+        }
+
+        let Recorder { movie, .. } = recorder;
+        movie
+    }
+
+    // Plays the movie back, pressing `extra` on one frame, and returns every reported divergence.
+    // The digit is drawn once the key is released, a frame after the press
+    fn play(movie: Movie, extra: Option<(u64, u8)>) -> Vec<u64> {
+        let mut processor = processor();
+        let mut player = Player::new(movie);
+        let mut diverged = Vec::new();
+
+        for frame in 0..FRAMES {
+            player.start_frame(&mut processor);
+            if let Some((at, key)) = extra {
+                processor.set_key(key, frame == at);
+            }
+            run_frame(&mut processor);
+            diverged.extend(player.end_frame(&processor));
+        }
+        assert!(player.finished());
+
+        diverged
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let movie = record();
+        let path = env::temp_dir().join(format!("chip8-movie-{}.txt", std::process::id()))
+            .to_string_lossy().into_owned();
+
+        movie.save(&path).unwrap();
+        let loaded = Movie::load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!((loaded.seed, loaded.random, loaded.speed, loaded.origin), (movie.seed, movie.random, movie.speed, 0x200));
+        assert_eq!(loaded.quirks, movie.quirks);
+        assert_eq!(loaded.inputs.len(), 4);
+        assert_eq!(loaded.hashes, movie.hashes);
+    }
+
+    #[test]
+    fn settings_are_required() {
+        let path = env::temp_dir().join(format!("chip8-movie-settings-{}.txt", std::process::id()))
+            .to_string_lossy().into_owned();
+
+        record().save(&path).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, contents.replace("quirk large_sprites false\n", "")).unwrap();
+        let loaded = Movie::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.err().unwrap(), format!("{}: missing `quirk large_sprites`", path));
+    }
+
+    #[test]
+    fn replays_without_diverging() {
+        assert!(play(record(), None).is_empty());
+    }
+
+    #[test]
+    fn reports_the_first_diverging_frame() {
+        assert_eq!(play(record(), Some((6, 0xA))), [7]);
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+
+/// Colors for the four combinations of the two XO-CHIP bitplanes.
+///
+/// Index 0 is used for pixels that are off in both planes, 1 for pixels only in the first plane,
+/// 2 for pixels only in the second plane and 3 for pixels in both planes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    /// RGBA, indexed as described above.
+    pub colors: [[u8; 4]; 4]
+}
+
+impl Palette {
+    /// Parses four comma-separated hex colors, e.g. `000000,FFFFFF,AAAAAA,555555`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let colors: Vec<[u8; 4]> = value.split(',').map(parse_color).collect::<Result<_, _>>()?;
+
+        let colors: [[u8; 4]; 4] = colors.try_into()
+            .map_err(|_| format!("expected 4 colors, got `{}`", value))?;
+
+        Ok(Palette { colors })
+    }
+
+    /// The RGBA color of a pixel value from the screen, 0 to 3.
+    pub fn color(&self, pixel: u8) -> [u8; 4] {
+        self.colors[(pixel & 0b11) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            colors: [
+                [0x00, 0x00, 0x00, 0xFF], // Black
+                [0xFF, 0xFF, 0xFF, 0xFF], // White
+                [0xAA, 0xAA, 0xAA, 0xFF], // Light gray
+                [0x55, 0x55, 0x55, 0xFF], // Dark gray
+            ]
+        }
+    }
+}
+
+/// Writes the colors the way [`Palette::parse`] reads them.
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let colors: Vec<String> = self.colors.iter()
+            .map(|[r, g, b, _]| format!("{:02X}{:02X}{:02X}", r, g, b))
+            .collect();
+        write!(f, "{}", colors.join(","))
+    }
+}
+
+/// Parses a color in the form RRGGBB, optionally prefixed with #.
+pub fn parse_color(value: &str) -> Result<[u8; 4], String> {
+    let hex = value.trim().trim_start_matches('#');
+    let rgb = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or(format!("`{}` is not a RRGGBB hex color", value.trim()))?;
+
+    Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 0xFF])
+}
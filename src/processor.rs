@@ -1,12 +1,49 @@
-use std::{fs::File, io::Read};
+use std::{fs, io::Read};
+
+use crate::audio::AudioPattern;
+use crate::database;
+use crate::error::Chip8Error;
+use crate::font::{BIG_FONTSET, BIG_FONT_ADDRESS, FONTSET};
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::rng::{RandomMode, Rng};
+use crate::snapshot::Snapshot;
+
+/// Size of the address space. XO-CHIP extends it to 64 KiB.
+pub const RAM_SIZE: usize = 0x10000;
+
+/// Width of the display buffer. It's always allocated for SUPER-CHIP's hi-res mode; in lo-res
+/// mode only the first 64*32 pixels are used.
+pub const MAX_WIDTH: usize = 128;
+/// Height of the display buffer.
+pub const MAX_HEIGHT: usize = 64;
+/// Where programs are loaded and start running, unless they ask for another address.
+pub const PROGRAM_START: usize = 0x200;
+
+/// What's on screen, for a frontend to draw.
+pub struct State {
+    /// Pixels row by row, `width` to a row. Each pixel has one bit per XO-CHIP bitplane.
+    pub vram: [u8; MAX_WIDTH*MAX_HEIGHT],
+    /// 64 in lo-res mode, 128 in SUPER-CHIP hi-res mode.
+    pub width: usize,
+    /// 32 in lo-res mode, 64 in SUPER-CHIP hi-res mode.
+    pub height: usize,
+    /// Whether the last instruction changed the screen.
+    pub vram_updated: bool
+}
 
-use rand::random;
+impl State {
+    /// The screen as text, one character per pixel: `.` for off, then `#`, `+` and `@` for the
+    /// XO-CHIP plane combinations.
+    pub fn ascii_art(&self) -> String {
+        let mut output = String::new();
 
-use crate::font::FONTSET;
+        for row in self.vram[..self.width * self.height].chunks(self.width) {
+            output.extend(row.iter().map(|pixel| ['.', '#', '+', '@'][(pixel & 3) as usize]));
+            output.push('\n');
+        }
 
-pub struct State {
-    pub vram: [bool; 64*32],
-    pub vram_updated: bool
+        output
+    }
 }
 
 enum ProgramCounter {
@@ -15,81 +52,373 @@ enum ProgramCounter {
     Nothing
 }
 
+/// The CHIP-8 machine: memory, registers, timers, the screen and the keypad.
 pub struct Processor {
-    ram: [u8; 4096],
+    ram: [u8; RAM_SIZE],
     v: [u8; 16],
     i: usize,
     pc: usize,
-    vram: [bool; 64*32],
+    vram: [u8; MAX_WIDTH*MAX_HEIGHT],
     vram_updated: bool,
+    planes: u8, // XO-CHIP bitplanes that drawing and clearing operate on
+    hires: bool, // SUPER-CHIP 128x64 mode
+    halted: bool, // Set by the SUPER-CHIP exit instruction
+    waiting_for_display: bool, // Set by DXYN with the display wait quirk, cleared by the next timer tick
+    /// Counts down at 60Hz. Programs use it for timing.
     pub delay_timer: u8,
+    /// Counts down at 60Hz. The beeper sounds while it's above zero.
     pub sound_timer: u8,
     stack: [usize; 16],
     sp: usize,
-    keys: [bool; 16]
+    keys: [bool; 16],
+    rpl: [u8; 16], // SUPER-CHIP "RPL user flags", named after the HP-48 calculator registers
+    audio_pattern: Option<[u8; 16]>, // XO-CHIP audio buffer, None until the program loads one
+    pitch: u8, // XO-CHIP audio pattern playback rate
+    rng: Rng,
+    quirks: Quirks,
+    rom_hash: Option<[u8; 20]>, // SHA-1 of the last rom loaded
+    writes: Vec<(usize, u8, u8)> // Address, old and new value of each byte the last instruction stored
 }
 
 impl Processor {
-    pub fn new() -> Self {
+    /// A machine with the fonts loaded and the program counter at 0x200, emulating the given
+    /// platform's quirks.
+    pub fn new(quirks: Quirks) -> Self {
         
         // Load fontset into ram
-        let mut ram = [0u8; 4096];
-        for i in 0..FONTSET.len() {
-            ram[i] = FONTSET[i];
-        }
+        let mut ram = [0u8; RAM_SIZE];
+        ram[..FONTSET.len()].copy_from_slice(&FONTSET);
+        ram[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + BIG_FONTSET.len()].copy_from_slice(&BIG_FONTSET);
 
         ram[0x1ff] = 1; // For Timendus/chip8-test-suite's quirks test
 
         Processor {
-            ram: ram,
+            ram,
             v: [0u8; 16], // Registers
             i: 0, // Index register
-            pc: 0x200, // Program counter
-            vram: [false; 64*32],
+            pc: PROGRAM_START, // Program counter
+            vram: [0u8; MAX_WIDTH*MAX_HEIGHT],
             vram_updated: false,
+            planes: 0b01,
+            hires: false,
+            halted: false,
+            waiting_for_display: false,
             delay_timer: 0u8,
             sound_timer: 0u8,
             stack: [0; 16],
             sp: 0, // Stack pointer
-            keys: [false; 16]
+            keys: [false; 16],
+            rpl: [0u8; 16],
+            audio_pattern: None,
+            pitch: 64, // 4000Hz
+            rng: Rng::new(RandomMode::Xorshift, rand::random()),
+            quirks,
+            rom_hash: None,
+            writes: Vec::new()
         } // Return empty instance of Processor
     }
 
-    fn push(&mut self, value: usize) {
-        self.stack[self.sp] = value;
+    fn push(&mut self, value: usize) -> Result<(), Chip8Error> {
+        let entry = self.stack.get_mut(self.sp).ok_or(Chip8Error::StackOverflow { pc: self.pc })?;
+        *entry = value;
         self.sp += 1;
+
+        Ok(())
     }
 
-    fn pop(&mut self) -> usize {
+    fn pop(&mut self) -> Result<usize, Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow { pc: self.pc });
+        }
+
         self.sp -= 1;
-        self.stack[self.sp]
+        Ok(self.stack[self.sp])
     }
 
-    pub fn load(&mut self, path: &String) {
-        let mut file = File::open(path).expect("[-] Could not open file");
-        let mut buffer = [0u8; 3584];
+    // The memory the platform had, e.g. 4 KiB on the VIP. Programs can't reach past it.
+    fn memory_size(&self) -> usize {
+        self.quirks.memory_size.min(RAM_SIZE)
+    }
+
+    fn read(&self, address: usize) -> Result<u8, Chip8Error> {
+        self.ram[..self.memory_size()].get(address).copied().ok_or(Chip8Error::MemoryOutOfRange { address, pc: self.pc })
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> Result<(), Chip8Error> {
+        let (pc, memory) = (self.pc, self.memory_size());
+        let byte = self.ram[..memory].get_mut(address).ok_or(Chip8Error::MemoryOutOfRange { address, pc })?;
+        self.writes.push((address, *byte, value));
+        *byte = value;
+
+        Ok(())
+    }
+
+    /// Reads a rom file into memory at 0x200. Returns the number of bytes loaded.
+    pub fn load(&mut self, path: &str) -> Result<usize, Chip8Error> {
+        self.load_bytes(&fs::read(path)?, PROGRAM_START)
+    }
 
-        let _ = file.read(&mut buffer);
+    /// Reads a rom from any reader, e.g. stdin, into memory at the given address.
+    pub fn load_from(&mut self, mut reader: impl Read, address: usize) -> Result<usize, Chip8Error> {
+        let mut rom = Vec::new();
+        reader.read_to_end(&mut rom)?;
+
+        self.load_bytes(&rom, address)
+    }
 
-        for i in 0..buffer.len() {
-            self.ram[0x200 + i] = buffer[i];
+    /// Copies a rom into memory at the given address and starts running it from there. Most
+    /// programs load at 0x200, but some platforms used another address, like 0x600 on the
+    /// ETI-660. The rom has to fit in the memory the platform had (see [`Quirks::memory_size`]),
+    /// so set the quirks first. Returns the number of bytes loaded.
+    pub fn load_bytes(&mut self, rom: &[u8], address: usize) -> Result<usize, Chip8Error> {
+        let memory = self.memory_size();
+        if !(PROGRAM_START..memory).contains(&address) {
+            return Err(Chip8Error::InvalidLoadAddress { address });
+        }
+
+        let max = memory - address;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max });
+        }
+
+        self.ram[address..address + rom.len()].copy_from_slice(rom);
+        self.pc = address;
+        self.rom_hash = Some(database::hash(rom));
+
+        Ok(rom.len())
+    }
+
+    /// The SHA-1 hash of the last rom loaded, which identifies it in a
+    /// [`Database`](crate::database::Database).
+    pub fn rom_hash(&self) -> Option<[u8; 20]> {
+        self.rom_hash
+    }
+
+    /// The quirks of the platform being emulated.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Switches to another platform's quirks, e.g. the ones a rom database recommends for the
+    /// rom that was just loaded.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Replaces the random number generator, e.g. with a seeded one for a reproducible run.
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.rng = rng;
+    }
+
+    /// Marks a key on the hex keypad (0x0 - 0xF) as pressed or released.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if let Some(state) = self.keys.get_mut(key as usize) {
+            *state = pressed;
         }
     }
     
-    pub fn tick(&mut self) -> State {
+    /// Runs a single instruction and returns the screen after it.
+    pub fn tick(&mut self) -> Result<State, Chip8Error> {
         // Emulation cycle
         self.vram_updated = false;
+        self.writes.clear();
 
-        let opcode = self.get_opcode();
-        self.run_opcode(opcode); 
+        if !self.halted && !self.waiting_for_display {
+            let opcode = self.get_opcode()?;
+            self.run_opcode(opcode)?; 
+        }
+
+        Ok(self.state())
+    }
+
+    /// The stores the last instruction made, as address, old and new value, including ones that
+    /// didn't change the byte.
+    pub(crate) fn writes(&self) -> &[(usize, u8, u8)] {
+        &self.writes
+    }
 
+    /// The current screen.
+    pub fn state(&self) -> State {
         State {
             vram: self.vram,
+            width: self.width(),
+            height: self.height(),
             vram_updated: self.vram_updated
         }
     }
 
+    /// The XO-CHIP audio pattern, if the program has loaded one.
+    pub fn audio_pattern(&self) -> Option<AudioPattern> {
+        self.audio_pattern.map(|bits| AudioPattern { bits, pitch: self.pitch })
+    }
+
+    /// True once the program has run the SUPER-CHIP exit instruction (00FD).
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// True while a draw is waiting for the next 60Hz interrupt (the display wait quirk). Ticks do
+    /// nothing until [`Processor::decrement_timers`] is called.
+    pub fn waiting_for_display(&self) -> bool {
+        self.waiting_for_display
+    }
+
+    /// Ends a display wait early, for a debugger stepping while the frame loop is paused.
+    pub fn skip_display_wait(&mut self) {
+        self.waiting_for_display = false;
+    }
+
+    /// Width of the screen in the current display mode.
+    pub fn width(&self) -> usize {
+        if self.hires { MAX_WIDTH } else { 64 }
+    }
+
+    /// Height of the screen in the current display mode.
+    pub fn height(&self) -> usize {
+        if self.hires { MAX_HEIGHT } else { 32 }
+    }
+
+    /// The program counter: the address of the next instruction.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The index register, used as a memory address.
+    pub fn i(&self) -> usize {
+        self.i
+    }
+
+    /// The general purpose registers V0 to VF.
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    /// The stack pointer: how many return addresses are on the stack.
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    /// The return addresses currently on the stack, oldest first.
+    pub fn stack(&self) -> &[usize] {
+        &self.stack[..self.sp]
+    }
+
+    /// All of memory.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // Which keys are held down, for the VIP cost model
+    pub(crate) fn keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    // The screen without copying it, row by row at the current width
+    pub(crate) fn vram(&self) -> &[u8] {
+        &self.vram[..self.width() * self.height()]
+    }
+
+    /// Captures the complete machine state, e.g. for a save state.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            ram: self.ram.to_vec(),
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            stack: self.stack,
+            sp: self.sp,
+            vram: self.vram.to_vec(),
+            planes: self.planes,
+            hires: self.hires,
+            halted: self.halted,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            keys: self.keys,
+            rpl: self.rpl,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            rng: self.rng.state(),
+            rng_mode: self.rng.mode()
+        }
+    }
+
+    /// Puts the machine back into a captured state. The quirks stay as they are.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.ram.copy_from_slice(&snapshot.ram);
+        self.v = snapshot.v;
+        self.i = snapshot.i;
+        self.pc = snapshot.pc;
+        self.stack = snapshot.stack;
+        self.sp = snapshot.sp;
+        self.vram.copy_from_slice(&snapshot.vram);
+        self.vram_updated = true; // The frontend has to redraw the restored screen
+        self.planes = snapshot.planes;
+        self.hires = snapshot.hires;
+        self.halted = snapshot.halted;
+        self.waiting_for_display = false; // Snapshots are taken between frames
+        self.delay_timer = snapshot.delay_timer;
+        self.sound_timer = snapshot.sound_timer;
+        self.keys = snapshot.keys;
+        self.rpl = snapshot.rpl;
+        self.audio_pattern = snapshot.audio_pattern;
+        self.pitch = snapshot.pitch;
+        self.rng.set_mode(snapshot.rng_mode);
+        self.rng.set_state(snapshot.rng);
+    }
+
+    fn clear_screen(&mut self) {
+        for pixel in self.vram.iter_mut() {
+            *pixel &= !self.planes;
+        }
+        self.vram_updated = true;
+    }
+
+    // Replaces the selected planes of a pixel with those of another pixel (or blank), leaving the
+    // other planes untouched.
+    fn scrolled_pixel(&self, pixel: u8, source: Option<u8>) -> u8 {
+        (pixel & !self.planes) | (source.unwrap_or(0) & self.planes)
+    }
+
+    fn scroll_down(&mut self, rows: usize) {
+        let (width, height) = (self.width(), self.height());
+
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let source = (y >= rows).then(|| self.vram[x + width * (y - rows)]);
+                self.vram[x + width * y] = self.scrolled_pixel(self.vram[x + width * y], source);
+            }
+        }
+        self.vram_updated = true;
+    }
+
+    fn scroll_right(&mut self, columns: usize) {
+        let (width, height) = (self.width(), self.height());
+
+        for y in 0..height {
+            for x in (0..width).rev() {
+                let source = (x >= columns).then(|| self.vram[x - columns + width * y]);
+                self.vram[x + width * y] = self.scrolled_pixel(self.vram[x + width * y], source);
+            }
+        }
+        self.vram_updated = true;
+    }
+
+    fn scroll_left(&mut self, columns: usize) {
+        let (width, height) = (self.width(), self.height());
+
+        for y in 0..height {
+            for x in 0..width {
+                let source = (x + columns < width).then(|| self.vram[x + columns + width * y]);
+                self.vram[x + width * y] = self.scrolled_pixel(self.vram[x + width * y], source);
+            }
+        }
+        self.vram_updated = true;
+    }
+
+    /// Counts the delay and sound timers down by one. Call this 60 times a second.
     pub fn decrement_timers(&mut self) {
+        self.waiting_for_display = false; // The interrupt the display wait was waiting for
+
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -99,36 +428,64 @@ impl Processor {
         } 
     }
 
-    fn get_opcode(&self) -> u16 {
-        (self.ram[self.pc] as u16) << 8 | (self.ram[self.pc + 1] as u16) // The opcode is two bytes
-                                                                         // long, so we add them
-                                                                         // together. (0xA2 and 0xF0 become 0xA2F0)
-    }
+    fn get_opcode(&self) -> Result<u16, Chip8Error> {
+        if self.pc + 1 >= self.memory_size() {
+            return Err(Chip8Error::PcOutOfRange { pc: self.pc });
+        }
 
-    fn run_opcode(&mut self, opcode: u16) { // https://en.wikipedia.org/wiki/CHIP-8#Opcode_table
-        //println!("Running opcode: {:04x}", opcode);
+        Ok((self.ram[self.pc] as u16) << 8 | (self.ram[self.pc + 1] as u16)) // The opcode is two bytes
+                                                                             // long, so we add them
+                                                                             // together. (0xA2 and 0xF0 become 0xA2F0)
+    }
 
-        let nibbles = ( // Half a byte is called a nibble
-            (opcode & 0xF000) >> 12,
-            (opcode & 0x0F00) >> 8,
-            (opcode & 0x00F0) >> 4,
-            (opcode & 0x000F)
-        );
+    fn run_opcode(&mut self, opcode: u16) -> Result<(), Chip8Error> { // https://en.wikipedia.org/wiki/CHIP-8#Opcode_table
+        let nibbles = nibbles(opcode);
         let change_pc = match nibbles {
             (0x0, 0x0, 0x0, 0x0) => { ProgramCounter::Next }, // NOP
+            (0x0, 0x0, 0xC, _) => { // Scrolls the display down by N pixels (SUPER-CHIP)
+                self.scroll_down(nibbles.3 as usize);
+
+                ProgramCounter::Next
+            },
             (0x0, 0x0, 0xE, 0x0) => { // CLEAR
-                self.vram = [false; 64*32]; 
-                self.vram_updated = true;
+                self.clear_screen();
 
                 ProgramCounter::Next
             },
             (0x0, 0x0, 0xE, 0xE) => { // RET
-                let ret_addr: usize = self.pop();
+                let ret_addr: usize = self.pop()?;
                 
                 self.pc = ret_addr;
             
                 ProgramCounter::Next
             },
+            (0x0, 0x0, 0xF, 0xB) => { // Scrolls the display right by 4 pixels (SUPER-CHIP)
+                self.scroll_right(4);
+
+                ProgramCounter::Next
+            },
+            (0x0, 0x0, 0xF, 0xC) => { // Scrolls the display left by 4 pixels (SUPER-CHIP)
+                self.scroll_left(4);
+
+                ProgramCounter::Next
+            },
+            (0x0, 0x0, 0xF, 0xD) => { // Exits the interpreter (SUPER-CHIP)
+                self.halted = true;
+
+                ProgramCounter::Nothing
+            },
+            (0x0, 0x0, 0xF, 0xE) => { // Switches to 64x32 lo-res mode (SUPER-CHIP)
+                self.hires = false;
+                self.clear_screen();
+
+                ProgramCounter::Next
+            },
+            (0x0, 0x0, 0xF, 0xF) => { // Switches to 128x64 hi-res mode (SUPER-CHIP)
+                self.hires = true;
+                self.clear_screen();
+
+                ProgramCounter::Next
+            },
             (0x1, _, _, _) => { // JMP
                 let nnn: u16 = opcode & 0x0FFF;
                 
@@ -139,7 +496,7 @@ impl Processor {
             (0x2, _, _ , _) => { // CALL
                 let nnn: u16 = opcode & 0x0FFF;
                 
-                self.push(self.pc);
+                self.push(self.pc)?;
                 self.pc = nnn as usize;
 
                 ProgramCounter::Nothing
@@ -174,6 +531,24 @@ impl Processor {
                     ProgramCounter::Next 
                 }
             },
+            (0x5, _, _, 0x2) => { // Stores Vx to Vy (in either order) in memory, starting at address I (XO-CHIP)
+                let registers = register_range(nibbles.1 as usize, nibbles.2 as usize);
+
+                for (offset, register) in registers.into_iter().enumerate() {
+                    self.write(self.i + offset, self.v[register])?;
+                }
+
+                ProgramCounter::Next
+            },
+            (0x5, _, _, 0x3) => { // Fills Vx to Vy (in either order) with values from memory, starting at address I (XO-CHIP)
+                let registers = register_range(nibbles.1 as usize, nibbles.2 as usize);
+
+                for (offset, register) in registers.into_iter().enumerate() {
+                    self.v[register] = self.read(self.i + offset)?;
+                }
+
+                ProgramCounter::Next
+            },
             (0x6, _, _, _) => { // Sets Vx to NN
                 let x: u16 = nibbles.1;
                 let nn: u16 = opcode & 0x00FF;
@@ -195,7 +570,6 @@ impl Processor {
                 let y: u16 = nibbles.2;
                 
                 self.v[x as usize] = self.v[y as usize];
-                self.v[0xF] = 0; // Clear VF flag
 
                 ProgramCounter::Next
             },
@@ -204,7 +578,9 @@ impl Processor {
                 let y: u16 = nibbles.2;
                 
                 self.v[x as usize] |= self.v[y as usize];
-                self.v[0xF] = 0; // Clear VF flag
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0; // Clear VF flag
+                }
 
                 ProgramCounter::Next
             },
@@ -213,7 +589,9 @@ impl Processor {
                 let y: u16 = nibbles.2;
                 
                 self.v[x as usize] &= self.v[y as usize];
-                self.v[0xF] = 0; // Clear VF flag
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0; // Clear VF flag
+                }
 
                 ProgramCounter::Next
             },
@@ -222,7 +600,9 @@ impl Processor {
                 let y: u16 = nibbles.2;
                 
                 self.v[x as usize] ^= self.v[y as usize];
-                self.v[0xF] = 0; // Clear VF flag
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0; // Clear VF flag
+                }
 
                 ProgramCounter::Next
             },
@@ -254,7 +634,9 @@ impl Processor {
                 let x: u16 = nibbles.1;
                 let y: u16 = nibbles.2;
 
-                self.v[x as usize] = self.v[y as usize];
+                if self.quirks.shift_uses_vy {
+                    self.v[x as usize] = self.v[y as usize];
+                }
 
                 let lsb = self.v[x as usize] & 0x1; // Least significant bit
                 
@@ -279,7 +661,9 @@ impl Processor {
                 let x: u16 = nibbles.1;
                 let y: u16 = nibbles.2;
                 
-                self.v[x as usize] = self.v[y as usize];
+                if self.quirks.shift_uses_vy {
+                    self.v[x as usize] = self.v[y as usize];
+                }
 
                 let msb = (self.v[x as usize] >> 7) & 0x1; // Most significant bit
                 
@@ -305,10 +689,11 @@ impl Processor {
 
                 ProgramCounter::Next
             },
-            (0xB, _, _, _) => { // Sets PC to the value of V0 plus NNN
+            (0xB, _, _, _) => { // Sets PC to the value of V0 plus NNN (or Vx plus XNN, depending on the platform)
                 let nnn: u16 = opcode & 0x0FFF;
+                let offset = if self.quirks.jump_uses_vx { self.v[nibbles.1 as usize] } else { self.v[0x0] };
 
-                self.pc = (self.v[0x0] as u16 + nnn) as usize;
+                self.pc = (offset as u16 + nnn) as usize;
                 
                 ProgramCounter::Nothing
             },
@@ -316,37 +701,66 @@ impl Processor {
                 let x: u16 = nibbles.1;
                 let nn: u16 = opcode & 0x00FF;
                 
-                let rng: u8 = random::<u8>();
+                let rng: u8 = self.rng.next_u8();
                 self.v[x as usize] = rng & (nn as u8); 
 
                 ProgramCounter::Next
             },
             (0xD, _, _, _) => { // Draws a sprite at coordinate (Vx, Vy) that has a width of 8 pixels and a height of N pixels.
-                let x_coord = self.v[nibbles.1 as usize] as u16;
-                let y_coord = self.v[nibbles.2 as usize] as u16;
-                let num_rows = nibbles.3;
+                                // If N is 0, draws a 16x16 sprite instead (SUPER-CHIP and XO-CHIP).
+                let (width, height) = (self.width(), self.height());
+                let x_coord = self.v[nibbles.1 as usize] as usize % width; // The starting position always wraps
+                let y_coord = self.v[nibbles.2 as usize] as usize % height;
+                let (num_rows, row_width) = match nibbles.3 {
+                    0 if self.hires || self.quirks.large_sprites => (16, 16),
+                    0 => (0, 8), // The VIP draws nothing, though it still clears VF and waits for the display
+                    n => (n as usize, 8),
+                };
             
                 let mut flipped = false;
-                
-                for y_line in 0..num_rows {
-                    let addr = self.i + (y_line as usize);
-                    let pixels = self.ram[addr as usize];
-                    for x_line in 0..8 {
-                        // Use a mask to fetch current pixel's bit. Only flip if a 1
-                        if (pixels & (0b1000_0000 >> x_line)) != 0 {
-                            // Sprites should wrap around screen, so apply modulo
-                            let x: usize = (x_coord + x_line) as usize % 64;
-                            let y: usize = (y_coord + y_line) as usize % 32;
-
-                            let idx = x + 64 * y;
-                            
-                            flipped |= self.vram[idx];
-                            self.vram[idx] ^= true;
-                            self.vram_updated = true; // So the renderer knows it should update the
-                                                      // screen
+                let mut addr = self.i;
+
+                // Each selected XO-CHIP plane gets its own copy of the sprite data, one after the other
+                for plane in [0b01u8, 0b10u8] {
+                    if self.planes & plane == 0 {
+                        continue;
+                    }
+
+                    for y_line in 0..num_rows {
+                        // 16 pixel wide sprites take up two bytes per row
+                        let pixels: u16 = if row_width == 16 {
+                            let row = (self.read(addr)? as u16) << 8 | self.read(addr + 1)? as u16;
+                            addr += 2;
+                            row
+                        } else {
+                            let row = (self.read(addr)? as u16) << 8;
+                            addr += 1;
+                            row
+                        };
+                        for x_line in 0..row_width {
+                            // Use a mask to fetch current pixel's bit. Only flip if a 1
+                            if (pixels & (0b1000_0000_0000_0000 >> x_line)) != 0 {
+                                let (mut x, mut y) = (x_coord + x_line, y_coord + y_line);
+                                if x >= width || y >= height {
+                                    if self.quirks.clip_sprites {
+                                        continue;
+                                    }
+
+                                    // Sprites should wrap around screen, so apply modulo
+                                    x %= width;
+                                    y %= height;
+                                }
+
+                                let idx = x + width * y;
+                                
+                                flipped |= self.vram[idx] & plane != 0;
+                                self.vram[idx] ^= plane;
+                                self.vram_updated = true; // So the renderer knows it should update the
+                                                          // screen
+                            }
                         }
                     }
-                } 
+                }
 
                 if flipped {
                     self.v[0xF] = 1;
@@ -354,11 +768,14 @@ impl Processor {
                     self.v[0xF] = 0;
                 }
 
+                // The VIP draws during the vertical blank interrupt, so nothing else runs until the next one
+                self.waiting_for_display = self.quirks.display_wait;
+
                 ProgramCounter::Next
             },
             (0xE, _, 0x9, 0xE) => { // Skip the next instruction if the key in Vx is pressed
                 let x: u16 = nibbles.1;
-                let vx: u8 = self.v[x as usize];
+                let vx: u8 = self.v[x as usize] & 0xF; // There are only 16 keys
 
                 if self.keys[vx as usize] {
                     ProgramCounter::Skip
@@ -368,7 +785,7 @@ impl Processor {
             },
             (0xE, _, 0xA, 0x1) => { // Skip the next instruction if the key in Vx is not pressed
                 let x: u16 = nibbles.1;
-                let vx: u8 = self.v[x as usize];
+                let vx: u8 = self.v[x as usize] & 0xF; // There are only 16 keys
 
                 if !self.keys[vx as usize] {
                     ProgramCounter::Skip
@@ -376,6 +793,27 @@ impl Processor {
                     ProgramCounter::Next
                 }
             },
+            (0xF, 0x0, 0x0, 0x0) => { // Sets I to the 16-bit address NNNN stored in the next two bytes (XO-CHIP)
+                self.i = (self.read(self.pc + 2)? as usize) << 8 | self.read(self.pc + 3)? as usize;
+                self.pc += 2; // Step over the address, then continue as usual
+
+                ProgramCounter::Next
+            },
+            (0xF, _, 0x0, 0x1) => { // Selects the bitplanes N for drawing and clearing (XO-CHIP)
+                self.planes = (nibbles.1 & 0b11) as u8;
+
+                ProgramCounter::Next
+            },
+            (0xF, 0x0, 0x0, 0x2) => { // Loads 16 bytes from memory at I into the audio pattern buffer (XO-CHIP)
+                let mut pattern = [0u8; 16];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read(self.i + offset)?;
+                }
+
+                self.audio_pattern = Some(pattern);
+
+                ProgramCounter::Next
+            },
             (0xF, _, 0x0, 0x7) => { // Sets Vx to the value of the delay timer.
                 let x: u16 = nibbles.1;
                 
@@ -425,10 +863,24 @@ impl Processor {
             },
             (0xF, _, 0x2, 0x9) => { // Set I to the font address of character Vx
                 let x: u16 = nibbles.1;
-                let character: u8 = self.v[x as usize];
+                let character: u8 = self.v[x as usize] & 0xF;
+
+                self.i = character as usize * 5; // Font sprites take up five bytes each, so
+                                                 // their address is just their value times 5.
+
+                ProgramCounter::Next
+            },
+            (0xF, _, 0x3, 0x0) => { // Set I to the big font address of character Vx (SUPER-CHIP)
+                let x: u16 = nibbles.1;
+                let character: u8 = self.v[x as usize] & 0xF;
+
+                self.i = BIG_FONT_ADDRESS + character as usize * 10; // Big font sprites are 8x10
 
-                self.i = (character * 5) as usize; // Font sprites take up five bytes each, so
-                                                   // their address is just their value times 5.
+                ProgramCounter::Next
+            },
+            (0xF, _, 0x3, 0xA) => { // Sets the audio pattern pitch to Vx (XO-CHIP)
+                let x: u16 = nibbles.1;
+                self.pitch = self.v[x as usize];
 
                 ProgramCounter::Next
             },
@@ -443,9 +895,9 @@ impl Processor {
                 let tens = ((vx / 10.0) % 10.0).floor() as u8;
                 let ones = (vx % 10.0) as u8;
                     
-                self.ram[self.i as usize] = hundreds;
-                self.ram[(self.i + 1) as usize] = tens;
-                self.ram[(self.i + 2) as usize] = ones;
+                self.write(self.i, hundreds)?;
+                self.write(self.i + 1, tens)?;
+                self.write(self.i + 2, ones)?;
 
                 ProgramCounter::Next
             },
@@ -453,10 +905,14 @@ impl Processor {
                 let x: u16 = nibbles.1;
 
                 for index in 0..=x {
-                    self.ram[self.i + index as usize] = self.v[index as usize];
+                    self.write(self.i + index as usize, self.v[index as usize])?;
                 }
 
-                self.i += (x + 1) as usize;
+                self.i += match self.quirks.memory_increment {
+                    MemoryIncrement::XPlusOne => (x + 1) as usize,
+                    MemoryIncrement::X => x as usize,
+                    MemoryIncrement::None => 0,
+                };
 
                 ProgramCounter::Next
             },
@@ -465,21 +921,580 @@ impl Processor {
                 let x: u16 = nibbles.1;
 
                 for index in 0..=x {
-                    self.v[index as usize] = self.ram[self.i + index as usize];
+                    self.v[index as usize] = self.read(self.i + index as usize)?;
                 }
 
-                self.i += (x + 1) as usize;
+                self.i += match self.quirks.memory_increment {
+                    MemoryIncrement::XPlusOne => (x + 1) as usize,
+                    MemoryIncrement::X => x as usize,
+                    MemoryIncrement::None => 0,
+                };
+
+                ProgramCounter::Next
+            },
+            (0xF, _, 0x7, 0x5) => { // Stores V0 to VX (including VX) in the RPL user flags (SUPER-CHIP)
+                let x: u16 = nibbles.1;
+
+                self.rpl[..=x as usize].copy_from_slice(&self.v[..=x as usize]);
 
                 ProgramCounter::Next
             },
-            (_, _, _, _) => { unimplemented!("[-] Unimplemented opcode: {:04x}", opcode); },
+            (0xF, _, 0x8, 0x5) => { // Fills V0 to VX (including VX) from the RPL user flags (SUPER-CHIP)
+                let x: u16 = nibbles.1;
+
+                self.v[..=x as usize].copy_from_slice(&self.rpl[..=x as usize]);
+
+                ProgramCounter::Next
+            },
+            (_, _, _, _) => { return Err(Chip8Error::UnknownOpcode { opcode, pc: self.pc }); },
         };
 
         match change_pc {
             ProgramCounter::Next => self.pc += 2,
-            ProgramCounter::Skip => self.pc += 4, // Skips next instruction
+            ProgramCounter::Skip => { // Skips next instruction
+                self.pc += 2;
+                self.pc += if self.get_opcode()? == 0xF000 { 4 } else { 2 }; // Long I loads take up four bytes
+            },
             ProgramCounter::Nothing => {},
         }
+
+        Ok(())
+    }
+
+}
+
+/// Splits an opcode into its four nibbles, most significant first.
+pub fn nibbles(opcode: u16) -> (u16, u16, u16, u16) {
+    ( // Half a byte is called a nibble
+        (opcode & 0xF000) >> 12,
+        (opcode & 0x0F00) >> 8,
+        (opcode & 0x00F0) >> 4,
+        (opcode & 0x000F)
+    )
+}
+
+// The registers from Vx to Vy, counting down if x > y
+fn register_range(x: usize, y: usize) -> Vec<usize> {
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A processor with the opcodes loaded at 0x200
+    fn processor_with(quirks: Quirks, opcodes: &[u16]) -> Processor {
+        let mut processor = Processor::new(quirks);
+        for (index, opcode) in opcodes.iter().enumerate() {
+            processor.ram[0x200 + index * 2..0x200 + index * 2 + 2].copy_from_slice(&opcode.to_be_bytes());
+        }
+        processor
+    }
+
+    fn run(opcodes: &[u16]) -> Processor {
+        run_with(Quirks::COSMAC_VIP, opcodes)
+    }
+
+    // Runs every opcode once, in order
+    fn run_with(quirks: Quirks, opcodes: &[u16]) -> Processor {
+        let mut processor = processor_with(quirks, opcodes);
+        for _ in opcodes {
+            processor.tick().unwrap();
+        }
+        processor
+    }
+
+    fn pixel(processor: &Processor, x: usize, y: usize) -> u8 {
+        processor.vram[x + processor.width() * y]
+    }
+
+    #[test]
+    fn load_bytes_starts_at_the_load_address() {
+        let mut processor = Processor::new(Quirks::COSMAC_VIP);
+        assert_eq!(processor.load_bytes(&[0x60, 0x42], 0x600).unwrap(), 2);
+        assert_eq!(processor.ram[0x600..0x602], [0x60, 0x42]);
+
+        processor.tick().unwrap();
+        assert_eq!((processor.pc, processor.v[0]), (0x602, 0x42));
+    }
+
+    #[test]
+    fn load_from_a_reader() {
+        let mut processor = Processor::new(Quirks::COSMAC_VIP);
+        assert_eq!(processor.load_from(&[1u8, 2, 3][..], PROGRAM_START).unwrap(), 3);
+        assert_eq!(processor.ram[0x200..0x204], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn load_hashes_the_rom() {
+        let mut processor = Processor::new(Quirks::COSMAC_VIP);
+        assert_eq!(processor.rom_hash(), None);
+
+        processor.load_bytes(b"abc", PROGRAM_START).unwrap();
+        let hash = processor.rom_hash().map(|hash| crate::database::format_hash(&hash));
+        assert_eq!(hash.as_deref(), Some("a9993e364706816aba3e25717850c26c9cd0d89d"));
+    }
+
+    #[test]
+    fn load_checks_the_size_and_address() {
+        let mut processor = Processor::new(Quirks::XO_CHIP);
+
+        let rom = vec![0; RAM_SIZE - 0x600 + 1];
+        assert!(matches!(processor.load_bytes(&rom, 0x600), Err(Chip8Error::RomTooLarge { max, .. }) if max == RAM_SIZE - 0x600));
+        assert!(matches!(processor.load_bytes(&rom[1..], 0x600), Ok(size) if size == RAM_SIZE - 0x600));
+
+        assert!(matches!(processor.load_bytes(&[0], 0x100), Err(Chip8Error::InvalidLoadAddress { address: 0x100 })));
+        assert!(matches!(processor.load_bytes(&[0], RAM_SIZE), Err(Chip8Error::InvalidLoadAddress { .. })));
+    }
+
+    #[test]
+    fn roms_have_to_fit_in_the_platforms_memory() {
+        let mut processor = Processor::new(Quirks::SCHIP);
+
+        assert!(matches!(processor.load_bytes(&[0; 3584], PROGRAM_START), Ok(3584)));
+        assert!(matches!(processor.load_bytes(&[0; 3585], PROGRAM_START), Err(Chip8Error::RomTooLarge { max: 3584, .. })));
+        assert!(matches!(processor.load_bytes(&[0], 0x1000), Err(Chip8Error::InvalidLoadAddress { address: 0x1000 })));
+    }
+
+    #[test]
+    fn programs_cant_reach_past_the_platforms_memory() {
+        // FX55 stores V0 and V1 at 0xFFF and 0x1000, the second of which the VIP doesn't have
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0xAFFF, 0xF155]);
+        processor.tick().unwrap();
+        assert!(matches!(processor.tick(), Err(Chip8Error::MemoryOutOfRange { address: 0x1000, pc: 0x202 })));
+
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0xAFFF, 0xF165]);
+        processor.tick().unwrap();
+        assert!(matches!(processor.tick(), Err(Chip8Error::MemoryOutOfRange { address: 0x1000, .. })));
+
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0x1FFE]);
+        processor.tick().unwrap();
+        assert!(processor.tick().is_ok()); // The last opcode in memory still runs
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0x6001, 0xBFFF]);
+        processor.tick().unwrap();
+        processor.tick().unwrap();
+        assert!(matches!(processor.tick(), Err(Chip8Error::PcOutOfRange { pc: 0x1000 })));
+
+        // XO-CHIP has all 64 KiB
+        let mut processor = processor_with(Quirks::XO_CHIP, &[0xAFFF, 0xF155]);
+        processor.tick().unwrap();
+        processor.tick().unwrap();
+        assert_eq!(processor.ram[0x1000], 0);
+    }
+
+    #[test]
+    fn nop_advances_pc() {
+        assert_eq!(run(&[0x0000]).pc, 0x202);
+    }
+
+    #[test]
+    fn cls_clears_the_selected_planes() {
+        let mut processor = processor_with(Quirks::XO_CHIP, &[0xF101, 0x00E0]);
+        processor.vram[0] = 0b11;
+        processor.tick().unwrap();
+        processor.tick().unwrap();
+
+        assert_eq!(processor.vram[0], 0b10);
+    }
+
+    #[test]
+    fn call_and_ret() {
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0x2300]);
+        processor.ram[0x300..0x302].copy_from_slice(&[0x00, 0xEE]);
+
+        processor.tick().unwrap();
+        assert_eq!((processor.pc, processor.stack()), (0x300, &[0x200][..]));
+
+        processor.tick().unwrap();
+        assert_eq!((processor.pc, processor.sp), (0x202, 0));
+    }
+
+    #[test]
+    fn ret_with_an_empty_stack_underflows() {
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0x00EE]);
+        assert!(matches!(processor.tick(), Err(Chip8Error::StackUnderflow { pc: 0x200 })));
+    }
+
+    #[test]
+    fn call_with_a_full_stack_overflows() {
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0x2200]); // Calls itself forever
+        for _ in 0..16 {
+            processor.tick().unwrap();
+        }
+        assert!(matches!(processor.tick(), Err(Chip8Error::StackOverflow { pc: 0x200 })));
+    }
+
+    #[test]
+    fn scd_scrolls_down() {
+        let mut processor = processor_with(Quirks::SCHIP, &[0x00C2]);
+        processor.vram[3] = 1;
+        processor.tick().unwrap();
+
+        assert_eq!((pixel(&processor, 3, 0), pixel(&processor, 3, 2)), (0, 1));
+    }
+
+    #[test]
+    fn scr_and_scl_scroll_sideways() {
+        let mut processor = processor_with(Quirks::SCHIP, &[0x00FB, 0x00FC, 0x00FC]);
+        processor.vram[4] = 1;
+
+        processor.tick().unwrap();
+        assert_eq!((pixel(&processor, 4, 0), pixel(&processor, 8, 0)), (0, 1));
+        processor.tick().unwrap();
+        assert_eq!(pixel(&processor, 4, 0), 1);
+        processor.tick().unwrap();
+        assert_eq!(pixel(&processor, 0, 0), 1);
+    }
+
+    #[test]
+    fn exit_halts() {
+        let mut processor = run_with(Quirks::SCHIP, &[0x00FD]);
+        assert!(processor.halted());
+
+        processor.tick().unwrap(); // Ticking a halted processor does nothing
+        assert_eq!(processor.pc, 0x200);
+    }
+
+    #[test]
+    fn high_and_low_switch_resolution() {
+        let processor = run_with(Quirks::SCHIP, &[0x00FF]);
+        assert_eq!((processor.width(), processor.height()), (128, 64));
+
+        let processor = run_with(Quirks::SCHIP, &[0x00FF, 0x00FE]);
+        assert_eq!((processor.width(), processor.height()), (64, 32));
+    }
+
+    #[test]
+    fn jp_jumps() {
+        assert_eq!(run(&[0x1ABC]).pc, 0xABC);
+    }
+
+    #[test]
+    fn se_and_sne_skip() {
+        assert_eq!(run(&[0x6005, 0x3005]).pc, 0x206); // 3XNN, equal
+        assert_eq!(run(&[0x6005, 0x3006]).pc, 0x204);
+        assert_eq!(run(&[0x6005, 0x4006]).pc, 0x206); // 4XNN, not equal
+        assert_eq!(run(&[0x6005, 0x4005]).pc, 0x204);
+        assert_eq!(run(&[0x6005, 0x6105, 0x5010]).pc, 0x208); // 5XY0, equal
+        assert_eq!(run(&[0x6005, 0x5010]).pc, 0x204);
+        assert_eq!(run(&[0x6005, 0x9010]).pc, 0x206); // 9XY0, not equal
+        assert_eq!(run(&[0x9010]).pc, 0x202);
+    }
+
+    #[test]
+    fn skips_step_over_long_i_loads() {
+        let mut processor = processor_with(Quirks::XO_CHIP, &[0x3000, 0xF000, 0x1234]);
+        processor.tick().unwrap();
+        assert_eq!(processor.pc, 0x206);
+    }
+
+    #[test]
+    fn save_and_load_register_ranges() {
+        let processor = run_with(Quirks::XO_CHIP, &[0x6101, 0x6202, 0x6303, 0xA300, 0x5132, 0x5313]);
+        assert_eq!(processor.ram[0x300..0x303], [1, 2, 3]);
+        assert_eq!(processor.v[1..4], [3, 2, 1]); // Read back in reverse
+        assert_eq!(processor.i, 0x300);
+    }
+
+    #[test]
+    fn ld_and_add_immediate() {
+        let processor = run(&[0x6AFF, 0x7A02]);
+        assert_eq!(processor.v[0xA], 0x01); // Wraps around
+        assert_eq!(processor.v[0xF], 0); // Without a carry flag
+    }
+
+    #[test]
+    fn logic_operations() {
+        assert_eq!(run(&[0x600C, 0x610A, 0x8010]).v[0], 0x0A);
+        assert_eq!(run(&[0x600C, 0x610A, 0x8011]).v[0], 0x0E);
+        assert_eq!(run(&[0x600C, 0x610A, 0x8012]).v[0], 0x08);
+        assert_eq!(run(&[0x600C, 0x610A, 0x8013]).v[0], 0x06);
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        assert_eq!(run_with(Quirks::COSMAC_VIP, &[0x6F05, 0x8011]).v[0xF], 0);
+        assert_eq!(run_with(Quirks::SCHIP, &[0x6F05, 0x8011]).v[0xF], 5);
+        assert_eq!(run_with(Quirks::COSMAC_VIP, &[0x6F05, 0x8010]).v[0xF], 5); // 8XY0 never resets it
+    }
+
+    #[test]
+    fn add_registers_sets_carry() {
+        let processor = run(&[0x60F0, 0x6120, 0x8014]);
+        assert_eq!((processor.v[0], processor.v[0xF]), (0x10, 1));
+
+        let processor = run(&[0x6010, 0x6120, 0x8014]);
+        assert_eq!((processor.v[0], processor.v[0xF]), (0x30, 0));
+    }
+
+    #[test]
+    fn subtract_sets_not_borrow() {
+        let processor = run(&[0x6010, 0x6120, 0x8015]); // 8XY5: Vx - Vy
+        assert_eq!((processor.v[0], processor.v[0xF]), (0xF0, 0));
+
+        let processor = run(&[0x6010, 0x6120, 0x8017]); // 8XY7: Vy - Vx
+        assert_eq!((processor.v[0], processor.v[0xF]), (0x10, 1));
+    }
+
+    #[test]
+    fn flag_result_wins_when_vf_is_the_target() {
+        assert_eq!(run(&[0x6FFF, 0x6101, 0x8F14]).v[0xF], 1);
+    }
+
+    #[test]
+    fn shifts() {
+        let processor = run_with(Quirks::COSMAC_VIP, &[0x6000, 0x6103, 0x8016]); // Shifts Vy
+        assert_eq!((processor.v[0], processor.v[0xF]), (0x01, 1));
+
+        let processor = run_with(Quirks::SCHIP, &[0x6003, 0x61FF, 0x8016]); // Shifts Vx in place
+        assert_eq!((processor.v[0], processor.v[0xF]), (0x01, 1));
+
+        let processor = run_with(Quirks::COSMAC_VIP, &[0x6000, 0x6181, 0x801E]);
+        assert_eq!((processor.v[0], processor.v[0xF]), (0x02, 1));
+
+        let processor = run_with(Quirks::SCHIP, &[0x6041, 0x6181, 0x801E]);
+        assert_eq!((processor.v[0], processor.v[0xF]), (0x82, 0));
+    }
+
+    #[test]
+    fn ld_i() {
+        assert_eq!(run(&[0xA123]).i, 0x123);
+    }
+
+    #[test]
+    fn jump_with_offset() {
+        assert_eq!(run_with(Quirks::COSMAC_VIP, &[0x6010, 0x6220, 0xB230]).pc, 0x240); // NNN + V0
+        assert_eq!(run_with(Quirks::SCHIP, &[0x6010, 0x6220, 0xB230]).pc, 0x250); // XNN + VX
+    }
+
+    #[test]
+    fn rnd_is_masked_and_reproducible() {
+        let random = |seed: u64| {
+            let mut processor = processor_with(Quirks::COSMAC_VIP, &[0xC00F, 0xC1FF, 0xC2FF, 0xC3FF]);
+            processor.set_rng(Rng::new(RandomMode::Xorshift, seed));
+            for _ in 0..4 {
+                processor.tick().unwrap();
+            }
+            processor.v
+        };
+
+        assert!(random(1)[0] <= 0x0F);
+        assert_eq!(random(1), random(1));
+        assert_ne!(random(1)[1..4], random(2)[1..4]);
+    }
+
+    #[test]
+    fn drw_draws_and_detects_collisions() {
+        // The font sprite for 0 is 0xF0, 0x90, 0x90, 0x90, 0xF0
+        let processor = run(&[0x6002, 0x6103, 0xA000, 0xD015]);
+        assert_eq!((pixel(&processor, 2, 3), pixel(&processor, 3, 4), pixel(&processor, 6, 3)), (1, 0, 0));
+        assert_eq!(processor.v[0xF], 0);
+        assert!(processor.vram_updated);
+
+        let processor = run_with(Quirks::SCHIP, &[0xA000, 0xD015, 0xD015]); // Drawing twice erases the sprite
+        assert!(processor.vram.iter().all(|pixel| *pixel == 0));
+        assert_eq!(processor.v[0xF], 1);
+    }
+
+    #[test]
+    fn display_wait_stalls_until_the_next_timer_tick() {
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0xD015, 0x6001]);
+        processor.tick().unwrap();
+        processor.tick().unwrap();
+        assert_eq!((processor.pc, processor.v[0]), (0x202, 0));
+        assert!(processor.waiting_for_display());
+
+        processor.decrement_timers();
+        processor.tick().unwrap();
+        assert_eq!(processor.v[0], 1);
+
+        assert_eq!(run_with(Quirks::SCHIP, &[0xD015, 0x6001]).v[0], 1); // Without the quirk nothing waits
+    }
+
+    #[test]
+    fn drw_clips_or_wraps_at_the_edges() {
+        let program = [0x603E, 0xA000, 0xD015];
+
+        let processor = run_with(Quirks::COSMAC_VIP, &program);
+        assert_eq!((pixel(&processor, 63, 0), pixel(&processor, 0, 0)), (1, 0));
+
+        let processor = run_with(Quirks::XO_CHIP, &program);
+        assert_eq!((pixel(&processor, 63, 0), pixel(&processor, 0, 0)), (1, 1));
+
+        // The starting position always wraps
+        let processor = run_with(Quirks::COSMAC_VIP, &[0x6041, 0xA000, 0xD015]);
+        assert_eq!(pixel(&processor, 1, 0), 1);
+    }
+
+    #[test]
+    fn drw_with_height_zero_draws_16x16_sprites() {
+        let mut processor = processor_with(Quirks::SCHIP, &[0x00FF, 0xA300, 0xD000]);
+        processor.ram[0x300..0x320].fill(0xFF);
+        for _ in 0..3 {
+            processor.tick().unwrap();
+        }
+
+        assert_eq!((pixel(&processor, 15, 15), pixel(&processor, 16, 15), pixel(&processor, 15, 16)), (1, 0, 0));
+    }
+
+    #[test]
+    fn drw_with_height_zero_draws_nothing_on_the_vip() {
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0x6F01, 0xA300, 0xD000]);
+        processor.ram[0x300..0x320].fill(0xFF);
+        for _ in 0..3 {
+            processor.tick().unwrap();
+        }
+
+        assert!(processor.vram.iter().all(|pixel| *pixel == 0));
+        assert_eq!(processor.v[0xF], 0);
+        assert!(processor.waiting_for_display);
+
+        // XO-CHIP draws the 16x16 sprite in low resolution too
+        let mut processor = processor_with(Quirks::XO_CHIP, &[0xA300, 0xD000]);
+        processor.ram[0x300..0x320].fill(0xFF);
+        processor.tick().unwrap();
+        processor.tick().unwrap();
+        assert_eq!((pixel(&processor, 15, 15), pixel(&processor, 16, 15)), (1, 0));
+    }
+
+    #[test]
+    fn skp_and_sknp() {
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0x6015, 0xE09E]); // Only the low nibble counts
+        processor.set_key(5, true);
+        processor.tick().unwrap();
+        processor.tick().unwrap();
+        assert_eq!(processor.pc, 0x206);
+
+        assert_eq!(run(&[0x6005, 0xE09E]).pc, 0x204);
+        assert_eq!(run(&[0x6005, 0xE0A1]).pc, 0x206);
+    }
+
+    #[test]
+    fn ld_i_long() {
+        let mut processor = processor_with(Quirks::XO_CHIP, &[0xF000, 0xBEEF]);
+        processor.tick().unwrap();
+        assert_eq!((processor.i, processor.pc), (0xBEEF, 0x204));
+    }
+
+    #[test]
+    fn plane_selects_the_drawing_planes() {
+        let processor = run_with(Quirks::XO_CHIP, &[0xF201, 0xA000, 0xD015]);
+        assert_eq!(pixel(&processor, 0, 0), 0b10);
+
+        // With both planes selected, each plane takes its own sprite data
+        let processor = run_with(Quirks::XO_CHIP, &[0xF301, 0xA000, 0xD011]);
+        assert_eq!((pixel(&processor, 0, 0), pixel(&processor, 1, 0)), (0b11, 0b01));
+    }
+
+    #[test]
+    fn audio_loads_the_pattern() {
+        let mut processor = processor_with(Quirks::XO_CHIP, &[0xA300, 0xF002, 0x6040, 0xF03A]);
+        processor.ram[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        for _ in 0..4 {
+            processor.tick().unwrap();
+        }
+
+        let pattern = processor.audio_pattern().unwrap();
+        assert_eq!((pattern.bits, pattern.pitch), ([0xAA; 16], 0x40));
+    }
+
+    #[test]
+    fn timers() {
+        let mut processor = run(&[0x6009, 0xF015, 0xF018]);
+        assert_eq!((processor.delay_timer, processor.sound_timer), (9, 9));
+
+        processor.decrement_timers();
+        processor.ram[0x206..0x208].copy_from_slice(&[0xF1, 0x07]);
+        processor.tick().unwrap();
+        assert_eq!(processor.v[1], 8);
+    }
+
+    #[test]
+    fn ld_k_waits_for_a_key() {
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0xF00A]);
+        processor.tick().unwrap();
+        assert_eq!(processor.pc, 0x200);
+
+        processor.set_key(0xB, true);
+        processor.tick().unwrap();
+        assert_eq!((processor.pc, processor.v[0]), (0x202, 0xB));
     }
 
+    #[test]
+    fn add_i() {
+        let processor = run(&[0xA0FF, 0x6002, 0xF01E]);
+        assert_eq!((processor.i, processor.v[0xF]), (0x101, 0));
+    }
+
+    #[test]
+    fn font_addresses() {
+        assert_eq!(run(&[0x601A, 0xF029]).i, 0xA * 5);
+        assert_eq!(run(&[0x6003, 0xF030]).i, BIG_FONT_ADDRESS + 3 * 10);
+    }
+
+    #[test]
+    fn bcd() {
+        let processor = run(&[0x60FE, 0xA300, 0xF033]);
+        assert_eq!(processor.ram[0x300..0x303], [2, 5, 4]);
+    }
+
+    #[test]
+    fn store_and_load_registers() {
+        let processor = run_with(Quirks::COSMAC_VIP, &[0x6007, 0x6108, 0xA300, 0xF155]);
+        assert_eq!((processor.ram[0x300..0x302].to_vec(), processor.i), (vec![7, 8], 0x302));
+
+        let processor = run_with(Quirks::CHIP_48, &[0x6007, 0x6108, 0xA300, 0xF155]);
+        assert_eq!(processor.i, 0x301);
+
+        let mut processor = processor_with(Quirks::SCHIP, &[0xA300, 0xF165]);
+        processor.ram[0x300..0x302].copy_from_slice(&[3, 4]);
+        processor.tick().unwrap();
+        processor.tick().unwrap();
+        assert_eq!((processor.v[0..2].to_vec(), processor.i), (vec![3, 4], 0x300));
+    }
+
+    #[test]
+    fn rpl_flags() {
+        let processor = run_with(Quirks::SCHIP, &[0x6005, 0x6106, 0xF175, 0x6000, 0x6100, 0xF185]);
+        assert_eq!(processor.v[0..2], [5, 6]);
+    }
+
+    #[test]
+    fn unknown_opcodes_are_errors() {
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[0x5001]);
+        assert!(matches!(processor.tick(), Err(Chip8Error::UnknownOpcode { opcode: 0x5001, pc: 0x200 })));
+    }
+
+    #[test]
+    fn snapshot_round_trips() {
+        let mut processor = run_with(Quirks::XO_CHIP, &[0x6042, 0xA123, 0x2300]);
+        let snapshot = Snapshot::from_bytes(&processor.snapshot().to_bytes()).unwrap();
+
+        let mut restored = Processor::new(Quirks::XO_CHIP);
+        restored.restore(&snapshot);
+        assert_eq!((restored.v, restored.i, restored.pc, restored.stack()), (processor.v, 0x123, 0x300, &[0x204][..]));
+
+        // The random number generator continues where it was
+        assert_eq!(restored.rng.next_u8(), processor.rng.next_u8());
+    }
+
+    #[test]
+    fn snapshot_keeps_the_random_mode() {
+        let mut processor = processor_with(Quirks::COSMAC_VIP, &[]);
+        processor.set_rng(Rng::new(RandomMode::Vip, 0x1234));
+        let snapshot = Snapshot::from_bytes(&processor.snapshot().to_bytes()).unwrap();
+
+        // A session using the other generator switches to the saved one
+        let mut restored = Processor::new(Quirks::COSMAC_VIP);
+        restored.set_rng(Rng::new(RandomMode::Xorshift, 1));
+        restored.restore(&snapshot);
+        assert_eq!(restored.rng.mode(), RandomMode::Vip);
+        for _ in 0..16 {
+            assert_eq!(restored.rng.next_u8(), processor.rng.next_u8());
+        }
+    }
 }
@@ -0,0 +1,131 @@
+use crate::processor::RAM_SIZE;
+
+/// How FX55 and FX65 leave the index register after copying registers to or from memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryIncrement {
+    /// I is left pointing past the last register (COSMAC VIP).
+    XPlusOne,
+    /// I is left pointing at the last register (CHIP-48).
+    X,
+    /// I is unchanged (SUPER-CHIP).
+    None
+}
+
+impl MemoryIncrement {
+    /// Parses the names used in movies and config files: `x+1`, `x` or `none`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "x+1" => Some(MemoryIncrement::XPlusOne),
+            "x" => Some(MemoryIncrement::X),
+            "none" => Some(MemoryIncrement::None),
+            _ => None,
+        }
+    }
+
+    /// The name [`MemoryIncrement::parse`] accepts.
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryIncrement::XPlusOne => "x+1",
+            MemoryIncrement::X => "x",
+            MemoryIncrement::None => "none",
+        }
+    }
+}
+
+/// Behaviors that differ between CHIP-8 platforms. The same ROM can run differently (or not at
+/// all) depending on which of these it was written for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    /// 8XY1, 8XY2 and 8XY3 set VF to 0.
+    pub vf_reset: bool,
+    /// 8XY6 and 8XYE copy Vy into Vx before shifting.
+    pub shift_uses_vy: bool,
+    /// How FX55 and FX65 change I.
+    pub memory_increment: MemoryIncrement,
+    /// BNNN is read as BXNN and jumps to XNN + Vx instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// DXYN clips sprites at the screen edges instead of wrapping them.
+    pub clip_sprites: bool,
+    /// DXY0 draws a 16x16 sprite in low resolution too. Without it, DXY0 only does so in the
+    /// SUPER-CHIP's high resolution mode and draws nothing otherwise, as on the COSMAC VIP.
+    pub large_sprites: bool,
+    /// DXYN waits for the next 60Hz interrupt, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+    /// Bytes of memory, which limits how big a rom can be: 4 KiB, or 64 KiB on XO-CHIP.
+    pub memory_size: usize
+}
+
+impl Quirks {
+    /// The original interpreter on the COSMAC VIP.
+    pub const COSMAC_VIP: Quirks = Quirks {
+        vf_reset: true,
+        shift_uses_vy: true,
+        memory_increment: MemoryIncrement::XPlusOne,
+        jump_uses_vx: false,
+        clip_sprites: true,
+        large_sprites: false,
+        display_wait: true,
+        memory_size: 0x1000
+    };
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub const CHIP_48: Quirks = Quirks {
+        vf_reset: false,
+        shift_uses_vy: false,
+        memory_increment: MemoryIncrement::X,
+        jump_uses_vx: true,
+        clip_sprites: true,
+        large_sprites: false,
+        display_wait: false,
+        memory_size: 0x1000
+    };
+
+    /// SUPER-CHIP 1.1.
+    pub const SCHIP: Quirks = Quirks {
+        vf_reset: false,
+        shift_uses_vy: false,
+        memory_increment: MemoryIncrement::None,
+        jump_uses_vx: true,
+        clip_sprites: true,
+        large_sprites: true,
+        display_wait: false,
+        memory_size: 0x1000
+    };
+
+    /// XO-CHIP, as implemented by Octo.
+    pub const XO_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        shift_uses_vy: true,
+        memory_increment: MemoryIncrement::XPlusOne,
+        jump_uses_vx: false,
+        clip_sprites: false,
+        large_sprites: true,
+        display_wait: false,
+        memory_size: RAM_SIZE
+    };
+
+    /// The names accepted by [`Quirks::preset`].
+    pub const PRESET_NAMES: [&str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+    /// Looks up a preset by the name used on the command line.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" => Some(Quirks::CHIP_48),
+            "schip" => Some(Quirks::SCHIP),
+            "xochip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+
+    /// The name of the preset these quirks match, if any.
+    pub fn preset_name(&self) -> Option<&'static str> {
+        Quirks::PRESET_NAMES.into_iter().find(|name| Quirks::preset(name) == Some(*self))
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::COSMAC_VIP
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+
+use crate::snapshot::Snapshot;
+
+/// A changed run of bytes: where it starts and what the bytes were before the change.
+type Delta = Vec<(usize, Vec<u8>)>;
+
+// How to step back one frame from the snapshot after it
+struct Frame {
+    registers: Snapshot, // Everything except ram and vram, which are left empty
+    ram: Delta,
+    vram: Delta
+}
+
+/// A ring buffer of the last few seconds of emulation.
+///
+/// Only the newest snapshot is kept in full. Older frames are stored as the bytes of ram and
+/// vram that changed since, so most frames cost a few hundred bytes instead of 72 KiB.
+pub struct Rewind {
+    frames: VecDeque<Frame>,
+    capacity: usize,
+    current: Option<Snapshot>
+}
+
+impl Rewind {
+    /// Keeps at most `capacity` frames of history.
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+            current: None
+        }
+    }
+
+    /// Adds the state at the end of a frame, dropping the oldest frame once the buffer is full.
+    pub fn record(&mut self, snapshot: Snapshot) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let Some(mut previous) = self.current.replace(snapshot) else {
+            return;
+        };
+        let current = self.current.as_ref().unwrap();
+
+        let ram = diff(&previous.ram, &current.ram);
+        let vram = diff(&previous.vram, &current.vram);
+        previous.ram = Vec::new();
+        previous.vram = Vec::new();
+
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(Frame { registers: previous, ram, vram });
+    }
+
+    /// Steps back up to `frames` frames and returns the state to restore, or None once the
+    /// history is used up.
+    pub fn step_back(&mut self, frames: usize) -> Option<&Snapshot> {
+        let mut stepped = false;
+        for _ in 0..frames {
+            let Some(frame) = self.frames.pop_back() else { break };
+            let current = self.current.as_mut()?;
+
+            let mut ram = std::mem::take(&mut current.ram);
+            let mut vram = std::mem::take(&mut current.vram);
+            apply(&mut ram, &frame.ram);
+            apply(&mut vram, &frame.vram);
+
+            *current = Snapshot { ram, vram, ..frame.registers };
+            stepped = true;
+        }
+
+        self.current.as_ref().filter(|_| stepped)
+    }
+
+    /// Forgets the history, e.g. after loading a save state.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.current = None;
+    }
+}
+
+// The runs of bytes that differ between old and new, holding the old bytes
+fn diff(old: &[u8], new: &[u8]) -> Delta {
+    let mut delta = Vec::new();
+    let mut index = 0;
+
+    while index < old.len() {
+        if old[index] == new[index] {
+            index += 1;
+            continue;
+        }
+
+        let start = index;
+        while index < old.len() && old[index] != new[index] {
+            index += 1;
+        }
+        delta.push((start, old[start..index].to_vec()));
+    }
+
+    delta
+}
+
+fn apply(bytes: &mut [u8], delta: &Delta) {
+    for (start, old) in delta {
+        bytes[*start..*start + old.len()].copy_from_slice(old);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Processor, Quirks};
+
+    // Each frame runs one pass of a loop that counts in V0, stores it at 0x300 and draws it
+    fn frames(count: usize) -> Vec<Snapshot> {
+        let mut processor = Processor::new(Quirks { display_wait: false, ..Quirks::COSMAC_VIP });
+        processor.load_bytes(&[0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0xD0, 0x11, 0x12, 0x00], 0x200).unwrap();
+
+        (0..count).map(|_| {
+            for _ in 0..5 {
+                processor.tick().unwrap();
+            }
+            processor.snapshot()
+        }).collect()
+    }
+
+    fn recorded(capacity: usize, snapshots: &[Snapshot]) -> Rewind {
+        let mut rewind = Rewind::new(capacity);
+        for snapshot in snapshots {
+            rewind.record(snapshot.clone());
+        }
+        rewind
+    }
+
+    #[test]
+    fn steps_back_to_earlier_frames() {
+        let snapshots = frames(10);
+        let mut rewind = recorded(60, &snapshots);
+
+        assert_eq!(rewind.step_back(3).unwrap().to_bytes(), snapshots[6].to_bytes());
+        assert_eq!(rewind.step_back(1).unwrap().to_bytes(), snapshots[5].to_bytes());
+        assert_eq!(rewind.step_back(100).unwrap().to_bytes(), snapshots[0].to_bytes());
+        assert!(rewind.step_back(1).is_none());
+    }
+
+    #[test]
+    fn drops_the_oldest_frames_when_full() {
+        let snapshots = frames(10);
+        let mut rewind = recorded(4, &snapshots);
+
+        assert_eq!(rewind.step_back(4).unwrap().to_bytes(), snapshots[5].to_bytes());
+        assert!(rewind.step_back(1).is_none());
+    }
+
+    #[test]
+    fn records_nothing_without_capacity() {
+        let mut rewind = recorded(0, &frames(3));
+
+        assert!(rewind.step_back(1).is_none());
+    }
+}
@@ -0,0 +1,202 @@
+/// How CXNN picks its random numbers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RandomMode {
+    /// A good general purpose generator.
+    Xorshift,
+    /// The COSMAC VIP interpreter's routine, for comparing against the original.
+    Vip
+}
+
+impl RandomMode {
+    /// Parses the names used on the command line and in movies: `xorshift` or `vip`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "xorshift" => Some(RandomMode::Xorshift),
+            "vip" => Some(RandomMode::Vip),
+            _ => None,
+        }
+    }
+
+    /// The name [`RandomMode::parse`] accepts.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RandomMode::Xorshift => "xorshift",
+            RandomMode::Vip => "vip",
+        }
+    }
+
+    // How save states store the mode
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            RandomMode::Xorshift => 0,
+            RandomMode::Vip => 1,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(RandomMode::Xorshift),
+            1 => Some(RandomMode::Vip),
+            _ => None,
+        }
+    }
+}
+
+// The second page (0x100-0x1FF) of the COSMAC VIP CHIP-8 interpreter, which its random number
+// routine reads as a table. Programs loaded at 0x200 never see it, so it isn't in our ram.
+const VIP_PAGE: [u8; 256] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x45, 0xA3, 0x98, 0x56, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95, 0xAC,
+    0x22, 0xDC, 0x12, 0x56, 0xD4, 0x06, 0xB8, 0xD4, 0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6, 0x8A,
+    0xF4, 0xAA, 0x3B, 0x28, 0x9A, 0xFC, 0x01, 0xBA, 0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F, 0xAA,
+    0x0A, 0xAA, 0xD4, 0xE6, 0x06, 0xBF, 0x93, 0xBE, 0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00, 0x5A,
+    0x0E, 0xF5, 0x3B, 0x4B, 0x56, 0x0A, 0xFC, 0x01, 0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C, 0x9F,
+    0x56, 0x2A, 0x2A, 0xD4, 0x00, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x5B, 0x12, 0xD4, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x6B, 0x12, 0xD4, 0x15, 0x85, 0x22, 0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86, 0xFA,
+    0x0F, 0xB5, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x82, 0x15, 0x15, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x88,
+    0xD4, 0x45, 0x07, 0x30, 0x8C, 0x45, 0x07, 0x30, 0x84, 0xE6, 0x62, 0x26, 0x45, 0xA3, 0x36, 0x88,
+    0xD4, 0x3E, 0x88, 0xD4, 0xF8, 0xF0, 0xA7, 0xE7, 0x45, 0xF4, 0xA5, 0x86, 0xFA, 0x0F, 0x3B, 0xB2,
+    0xFC, 0x01, 0xB5, 0xD4, 0x45, 0x56, 0xD4, 0x45, 0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F, 0x3A,
+    0xC4, 0x07, 0x56, 0xD4, 0xAF, 0x22, 0xF8, 0xD3, 0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07, 0xD2,
+    0x56, 0xF8, 0xFF, 0xA6, 0xF8, 0x00, 0x7E, 0x56, 0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99, 0xEE,
+    0xF4, 0x56, 0x76, 0xE6, 0xF4, 0xB9, 0x56, 0x45, 0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA, 0x0F,
+    0xBA, 0xD4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x4B, // Ends with 00E0 004B, which the interpreter runs at startup
+];
+
+/// The random number generator behind CXNN. It's owned by the processor, so runs with the same
+/// seed are reproducible and the state can be saved and restored along with everything else.
+#[derive(Clone, Copy)]
+pub struct Rng {
+    mode: RandomMode,
+    state: u64
+}
+
+impl Rng {
+    /// Creates a generator. The same mode and seed always give the same numbers.
+    pub fn new(mode: RandomMode, seed: u64) -> Self {
+        let state = match mode {
+            RandomMode::Xorshift => splitmix64(seed), // Spreads out small seeds like 1, 2, 3
+            RandomMode::Vip => seed & 0xFFFF,
+        };
+
+        let mut rng = Rng { mode, state: 0 };
+        rng.set_state(state);
+        rng
+    }
+
+    /// Which generator this is.
+    pub fn mode(&self) -> RandomMode {
+        self.mode
+    }
+
+    /// The generator's internal state, for save states. The VIP generator only uses 16 bits.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Restores a state from [`Rng::state`].
+    pub fn set_state(&mut self, state: u64) {
+        self.state = match self.mode {
+            RandomMode::Xorshift if state == 0 => 1, // Xorshift gets stuck on zero
+            RandomMode::Xorshift => state,
+            RandomMode::Vip => state & 0xFFFF,
+        };
+    }
+
+    /// Switches generator, e.g. to match a save state. The state is reset by the caller.
+    pub fn set_mode(&mut self, mode: RandomMode) {
+        self.mode = mode;
+    }
+
+    /// The next random byte, before CXNN masks it with NN.
+    pub fn next_u8(&mut self) -> u8 {
+        match self.mode {
+            RandomMode::Xorshift => {
+                // xorshift64* (Marsaglia, Vigna)
+                self.state ^= self.state >> 12;
+                self.state ^= self.state << 25;
+                self.state ^= self.state >> 27;
+                (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+            },
+            RandomMode::Vip => {
+                // The VIP keeps a 16-bit seed in register R9. Each call increments it, adds the
+                // interpreter byte at 0x100 + the low half to the high half, then adds half of
+                // that sum to itself. The halving is SHRC, which shifts the first addition's carry
+                // into bit 7. The result becomes the new high half.
+                let [high, low] = (self.state as u16).wrapping_add(1).to_be_bytes();
+                let (sum, carry) = high.overflowing_add(VIP_PAGE[low as usize]);
+                let high = sum.wrapping_add((sum >> 1) | ((carry as u8) << 7));
+                self.state = u16::from_be_bytes([high, low]) as u64;
+                high
+            },
+        }
+    }
+}
+
+// Turns a seed into a well mixed starting state (Vigna's SplitMix64)
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(mut rng: Rng, count: usize) -> Vec<u8> {
+        (0..count).map(|_| rng.next_u8()).collect()
+    }
+
+    #[test]
+    fn xorshift_repeats_for_the_same_seed() {
+        let first = bytes(Rng::new(RandomMode::Xorshift, 1), 64);
+
+        assert_eq!(first, bytes(Rng::new(RandomMode::Xorshift, 1), 64));
+        assert_ne!(first, bytes(Rng::new(RandomMode::Xorshift, 2), 64));
+        assert!(first.iter().any(|byte| *byte != first[0]));
+    }
+
+    #[test]
+    fn vip_mode_follows_the_interpreter_routine() {
+        let mut rng = Rng::new(RandomMode::Vip, 0);
+
+        // R9 = 0x0001: 0x00 + VIP_PAGE[0x01] = 0x00, plus half of it is still 0x00
+        assert_eq!(rng.next_u8(), 0x00);
+        rng.set_state(0x0004);
+        // R9 = 0x0005: 0x00 + 0x45 = 0x45, plus 0x22 is 0x67
+        assert_eq!(rng.next_u8(), 0x67);
+        assert_eq!(rng.state(), 0x6705);
+    }
+
+    #[test]
+    fn vip_mode_shifts_the_carry_into_the_half() {
+        let mut rng = Rng::new(RandomMode::Vip, 0xF004);
+
+        // R9 = 0xF005: 0xF0 + 0x45 = 0x135, so 0x35 with a carry, plus (0x35 >> 1) | 0x80 is 0xCF
+        assert_eq!(bytes(rng, 4), [0xCF, 0x2B, 0x24, 0xB7]);
+        rng.next_u8();
+        assert_eq!(rng.state(), 0xCF05);
+    }
+
+    #[test]
+    fn vip_mode_is_not_constant() {
+        let output = bytes(Rng::new(RandomMode::Vip, 0), 256);
+
+        let mut distinct = output.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() > 64, "only {} distinct values", distinct.len());
+    }
+
+    #[test]
+    fn vip_state_is_sixteen_bits() {
+        let mut rng = Rng::new(RandomMode::Vip, 0x1_2345);
+        assert_eq!(rng.state(), 0x2345);
+
+        rng.set_state(0xFFFF);
+        rng.next_u8();
+        assert_eq!(rng.state() & 0xFF, 0x00);
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use chip8::frame::CpuSpeed;
+use chip8::palette::Palette;
+use chip8::quirks::Quirks;
+
+use crate::config::Config;
+
+/// Settings for a single rom, kept next to it in a file with the same name and a `.cfg`
+/// extension, e.g. `pong.cfg` for `pong.ch8`. Options given on the command line win over it.
+///
+/// The file has the same format as `config.toml`, limited to the settings that differ between
+/// roms: `timing.speed`, `display.palette` and the `[quirks]` section.
+/// ```toml
+/// [timing]
+/// speed = 400
+/// ```
+#[derive(Default)]
+pub struct RomConfig {
+    pub speed: Option<CpuSpeed>,
+    pub quirks: Option<Quirks>, // Starting from `quirks.platform`, as in config.toml
+    pub palette: Option<Palette>
+}
+
+impl RomConfig {
+    /// Loads the config for a rom, or an empty one if the rom doesn't have a config file or was
+    /// read from stdin (`-`).
+    pub fn load(rom: &str) -> Result<Self, String> {
+        let path = config_path(rom);
+        if rom == "-" || !path.exists() {
+            return Ok(RomConfig::default());
+        }
+
+        let path = path.display().to_string();
+        let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+        RomConfig::parse(&contents).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let config = Config::parse(contents)?;
+        let given = |name: &str| config.given.iter().any(|setting| setting == name);
+
+        if let Some(setting) = config.given.iter()
+            .find(|setting| !(*setting == "timing.speed" || *setting == "display.palette" || setting.starts_with("quirks.")))
+        {
+            return Err(format!("{} can only be set in config.toml, not for a single rom", setting));
+        }
+
+        Ok(RomConfig {
+            speed: given("timing.speed").then_some(config.defaults.speed),
+            quirks: config.given.iter().any(|setting| setting.starts_with("quirks.")).then_some(config.defaults.quirks),
+            palette: given("display.palette").then_some(config.defaults.palette)
+        })
+    }
+}
+
+fn config_path(rom: &str) -> PathBuf {
+    Path::new(rom).with_extension("cfg")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_per_rom_settings() {
+        let config = RomConfig::parse("[timing]\nspeed = 400\n[quirks]\nplatform = \"schip\"\nclip_sprites = false").unwrap();
+
+        assert_eq!(config.speed, Some(CpuSpeed::Hz(400)));
+        assert_eq!(config.quirks, Some(Quirks { clip_sprites: false, ..Quirks::SCHIP }));
+        assert_eq!(config.palette, None);
+
+        let config = RomConfig::parse("").unwrap();
+        assert_eq!((config.speed, config.quirks, config.palette), (None, None, None));
+    }
+
+    #[test]
+    fn rejects_emulator_wide_settings() {
+        assert_eq!(
+            RomConfig::parse("[display]\nscale = 4").err().unwrap(),
+            "display.scale can only be set in config.toml, not for a single rom"
+        );
+        assert_eq!(RomConfig::parse("[timing]\nspeed = 0").err().unwrap(), "timing.speed must be \"vip\" or between 1 and 1000000 instructions per second");
+    }
+}
@@ -0,0 +1,285 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::error::Chip8Error;
+use crate::processor::{MAX_HEIGHT, MAX_WIDTH, RAM_SIZE};
+use crate::rng::RandomMode;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+
+/// The format version written in the header of new save states.
+// Bump this whenever the layout below changes, and teach `from_bytes` to read the old layout
+pub const VERSION: u16 = 1;
+
+/// Everything needed to put a processor back exactly where it was.
+///
+/// Quirks aren't included: they describe the platform being emulated, not the program's state.
+#[derive(Clone)]
+pub struct Snapshot {
+    /// All of memory, including the interpreter and fonts below 0x200.
+    pub ram: Vec<u8>,
+    /// The registers V0 to VF.
+    pub v: [u8; 16],
+    /// The index register.
+    pub i: usize,
+    /// The program counter.
+    pub pc: usize,
+    /// Return addresses, of which the first `sp` are in use.
+    pub stack: [usize; 16],
+    /// The number of return addresses on the stack.
+    pub sp: usize,
+    /// One byte per pixel of the largest screen, holding a bit for each XO-CHIP plane.
+    pub vram: Vec<u8>,
+    /// The XO-CHIP bitplanes that drawing and clearing operate on.
+    pub planes: u8,
+    /// Whether the SUPER-CHIP high resolution mode is on.
+    pub hires: bool,
+    /// Set by the SUPER-CHIP exit instruction, 00FD.
+    pub halted: bool,
+    /// The delay timer, in 60ths of a second.
+    pub delay_timer: u8,
+    /// The sound timer, in 60ths of a second.
+    pub sound_timer: u8,
+    /// Which hex keys are held down.
+    pub keys: [bool; 16],
+    /// The SUPER-CHIP flag registers, saved and loaded by FX75 and FX85.
+    pub rpl: [u8; 16],
+    /// The XO-CHIP audio pattern, once F002 has loaded one.
+    pub audio_pattern: Option<[u8; 16]>,
+    /// The XO-CHIP pitch register, set by FX3A.
+    pub pitch: u8,
+    /// The random number generator's state.
+    pub rng: u64,
+    /// Which generator `rng` belongs to.
+    pub rng_mode: RandomMode
+}
+
+impl Snapshot {
+    /// Serializes the snapshot. Multi-byte values are little-endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RAM_SIZE + MAX_WIDTH * MAX_HEIGHT + 256);
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+        bytes.extend_from_slice(&self.ram);
+        bytes.extend_from_slice(&self.v);
+        bytes.extend_from_slice(&(self.i as u64).to_le_bytes()); // FX1E can move I anywhere
+        bytes.extend_from_slice(&(self.pc as u32).to_le_bytes());
+        for address in self.stack {
+            bytes.extend_from_slice(&(address as u32).to_le_bytes());
+        }
+        bytes.push(self.sp as u8);
+        bytes.extend_from_slice(&self.vram);
+        bytes.push(self.planes);
+        bytes.push(self.hires as u8);
+        bytes.push(self.halted as u8);
+        bytes.push(self.delay_timer);
+        bytes.push(self.sound_timer);
+        bytes.extend(self.keys.map(|pressed| pressed as u8));
+        bytes.extend_from_slice(&self.rpl);
+        match self.audio_pattern {
+            Some(pattern) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&pattern);
+            },
+            None => bytes.push(0),
+        }
+        bytes.push(self.pitch);
+        bytes.extend_from_slice(&self.rng.to_le_bytes());
+        bytes.push(self.rng_mode.id());
+
+        bytes
+    }
+
+    /// Reads a snapshot written by [`Snapshot::to_bytes`], in this or an older format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Chip8Error> {
+        let mut reader = Reader { bytes, offset: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Chip8Error::InvalidSnapshot("not a save state".to_string()));
+        }
+
+        let version = u16::from_le_bytes(reader.array()?);
+        let snapshot = match version {
+            1 => reader.read_v1()?,
+            // Older layouts get their own branch here, filling in defaults for what they lack
+            _ => return Err(Chip8Error::UnsupportedSnapshotVersion { version }),
+        };
+
+        if reader.offset != bytes.len() {
+            return Err(Chip8Error::InvalidSnapshot("trailing data".to_string()));
+        }
+        // I isn't checked: it can legitimately point anywhere, and memory accesses through it are
+        if snapshot.sp > snapshot.stack.len() || snapshot.pc >= RAM_SIZE {
+            return Err(Chip8Error::InvalidSnapshot("registers out of range".to_string()));
+        }
+
+        Ok(snapshot)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8], Chip8Error> {
+        let bytes = self.bytes.get(self.offset..self.offset + length)
+            .ok_or(Chip8Error::InvalidSnapshot("file is truncated".to_string()))?;
+        self.offset += length;
+
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Chip8Error> {
+        Ok(self.take(N)?.try_into().unwrap()) // take always returns N bytes
+    }
+
+    fn byte(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn address(&mut self) -> Result<usize, Chip8Error> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn read_v1(&mut self) -> Result<Snapshot, Chip8Error> {
+        let ram = self.take(RAM_SIZE)?.to_vec();
+        let v = self.array()?;
+        let i = u64::from_le_bytes(self.array()?) as usize;
+        let pc = self.address()?;
+        let mut stack = [0; 16];
+        for address in stack.iter_mut() {
+            *address = self.address()?;
+        }
+        let sp = self.byte()? as usize;
+        let vram = self.take(MAX_WIDTH * MAX_HEIGHT)?.to_vec();
+        let planes = self.byte()?;
+        let hires = self.byte()? != 0;
+        let halted = self.byte()? != 0;
+        let delay_timer = self.byte()?;
+        let sound_timer = self.byte()?;
+        let keys = self.array::<16>()?.map(|pressed| pressed != 0);
+        let rpl = self.array()?;
+        let audio_pattern = match self.byte()? {
+            0 => None,
+            _ => Some(self.array()?),
+        };
+        let pitch = self.byte()?;
+        let rng = u64::from_le_bytes(self.array()?);
+        let rng_mode = RandomMode::from_id(self.byte()?)
+            .ok_or(Chip8Error::InvalidSnapshot("unknown random number generator".to_string()))?;
+
+        Ok(Snapshot {
+            ram, v, i, pc, stack, sp, vram, planes, hires, halted, delay_timer, sound_timer,
+            keys, rpl, audio_pattern, pitch, rng, rng_mode
+        })
+    }
+}
+
+/// Save states live next to the rom, e.g. `pong.state1` for slot 1 of `pong.ch8`.
+pub fn slot_path(rom: &str, slot: u8) -> PathBuf {
+    Path::new(rom).with_extension(format!("state{}", slot))
+}
+
+/// Writes a save state file.
+pub fn save(snapshot: &Snapshot, path: &Path) -> Result<(), Chip8Error> {
+    fs::write(path, snapshot.to_bytes())?;
+    Ok(())
+}
+
+/// Reads a save state file.
+pub fn load(path: &Path) -> Result<Snapshot, Chip8Error> {
+    Snapshot::from_bytes(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Processor, Quirks};
+
+    const I_OFFSET: usize = MAGIC.len() + 2 + RAM_SIZE + 16;
+    const PC_OFFSET: usize = I_OFFSET + 8;
+    const SP_OFFSET: usize = PC_OFFSET + 4 + 16 * 4;
+
+    fn bytes() -> Vec<u8> {
+        let mut processor = Processor::new(Quirks::COSMAC_VIP);
+        processor.load_bytes(&[0x60, 0x2A, 0x12, 0x02], 0x200).unwrap();
+        processor.tick().unwrap();
+
+        processor.snapshot().to_bytes()
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        match Snapshot::from_bytes(bytes) {
+            Err(Chip8Error::InvalidSnapshot(reason)) => reason,
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("the snapshot was accepted"),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let bytes = bytes();
+        let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+
+        assert_eq!((snapshot.v[0], snapshot.pc), (0x2A, 0x202));
+        assert_eq!(snapshot.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = bytes();
+        bytes[0] = b'X';
+
+        assert_eq!(error(&bytes), "not a save state");
+    }
+
+    #[test]
+    fn rejects_truncated_and_padded_files() {
+        let bytes = bytes();
+
+        assert_eq!(error(&bytes[..bytes.len() - 1]), "file is truncated");
+        assert_eq!(error(&bytes[..3]), "file is truncated");
+        assert_eq!(error(&[bytes.as_slice(), &[0]].concat()), "trailing data");
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut bytes = bytes();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes),
+            Err(Chip8Error::UnsupportedSnapshotVersion { version }) if version == VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_registers_out_of_range() {
+        let mut bytes = bytes();
+        bytes[SP_OFFSET] = 17;
+        assert_eq!(error(&bytes), "registers out of range");
+
+        let mut bytes = self::bytes();
+        bytes[PC_OFFSET..PC_OFFSET + 4].copy_from_slice(&(RAM_SIZE as u32).to_le_bytes());
+        assert_eq!(error(&bytes), "registers out of range");
+    }
+
+    #[test]
+    fn keeps_i_past_the_end_of_memory() {
+        let mut bytes = bytes();
+        bytes[I_OFFSET..I_OFFSET + 8].copy_from_slice(&0x1_0000_0005u64.to_le_bytes());
+
+        assert_eq!(Snapshot::from_bytes(&bytes).unwrap().i, 0x1_0000_0005);
+    }
+
+    #[test]
+    fn rejects_unknown_random_number_generators() {
+        let mut bytes = bytes();
+        *bytes.last_mut().unwrap() = 0xFF;
+
+        assert_eq!(error(&bytes), "unknown random number generator");
+    }
+}
@@ -0,0 +1,60 @@
+use std::{collections::BTreeMap, fmt::Write, fs};
+
+/// Label addresses written by the assembler, so the debugger can show names instead of numbers.
+///
+/// The file format has one symbol per line: a hex address and a name, e.g. `022a draw_player`.
+#[derive(Default)]
+pub struct Symbols {
+    names: BTreeMap<usize, String>
+}
+
+impl Symbols {
+    /// Reads a symbol file. Errors name the file and line.
+    pub fn load(path: &String) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut symbols = Symbols::default();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (address, name) = line.split_once(char::is_whitespace)
+                .and_then(|(address, name)| Some((usize::from_str_radix(address, 16).ok()?, name.trim())))
+                .ok_or(format!("{}:{}: expected `<hex address> <name>`", path, line_number + 1))?;
+            symbols.insert(address, name.to_string());
+        }
+
+        Ok(symbols)
+    }
+
+    /// Names an address, replacing any name it had.
+    pub fn insert(&mut self, address: usize, name: String) {
+        self.names.insert(address, name);
+    }
+
+    /// The name of an address, if it has one.
+    pub fn name(&self, address: usize) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// The address with this name, if there is one.
+    pub fn address(&self, name: &str) -> Option<usize> {
+        self.names.iter().find(|(_, symbol)| *symbol == name).map(|(address, _)| *address)
+    }
+
+    /// Every symbol, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.names.iter().map(|(address, name)| (*address, name.as_str()))
+    }
+
+    /// Formats the symbols in the same format `load` reads.
+    pub fn to_file_contents(&self) -> String {
+        let mut output = String::new();
+        for (address, name) in self.iter() {
+            let _ = writeln!(output, "{:04x} {}", address, name);
+        }
+        output
+    }
+}
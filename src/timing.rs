@@ -0,0 +1,173 @@
+use crate::processor::{Processor, nibbles};
+
+/// Machine cycles per 60Hz frame on the COSMAC VIP: its 1.76MHz CDP1802 takes 8 clock periods per
+/// machine cycle.
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+/// Machine cycles the CDP1861 video chip takes every frame to fetch the 128 scanlines of 8 bytes
+/// it displays. The interpreter doesn't run while it does.
+pub const VIP_DISPLAY_CYCLES: u32 = 128 * 8;
+/// Machine cycles left for the interpreter each frame.
+pub const VIP_INTERPRETER_CYCLES: u32 = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+
+// Fetching, decoding and dispatching an instruction, before it does any work
+const FETCH: u32 = 68;
+// 0NNN calls a machine code routine, which 00E0 and 00EE are in the interpreter, and dispatches
+// through a shorter path
+const CALL_FETCH: u32 = 40;
+
+/// How many machine cycles the COSMAC VIP interpreter spends on the instruction at the program
+/// counter, given the current registers, keys and screen.
+///
+/// The costs are counted from the interpreter's code as disassembled in Laurence Scotford's
+/// "Chip-8 on the COSMAC VIP" articles, where every CDP1802 instruction it uses takes 2 machine
+/// cycles. They include the data dependent parts: taken skips, carries out of the low byte of I,
+/// how many registers `FX55` copies, the digits `FX33` counts out and everything `DXYN` does.
+/// `FX0A` doesn't count the time spent in the VIP monitor's keypad routine, and SUPER-CHIP and
+/// XO-CHIP instructions didn't exist on the VIP and get a nominal cost.
+pub fn vip_cycles(processor: &Processor) -> u32 {
+    let pc = processor.pc();
+    let Some(bytes) = processor.ram().get(pc..pc + 2) else {
+        return FETCH; // The processor reports the bad address when it runs
+    };
+    let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+    let (_, x, y, n) = nibbles(opcode);
+    let (vx, vy) = (processor.v()[x as usize], processor.v()[y as usize]);
+    let nn = opcode as u8;
+    let key = processor.keys()[(vx & 0xF) as usize];
+    let skip = |taken: bool| if taken { 4 } else { 0 };
+
+    match nibbles(opcode) {
+        (0x0, 0x0, 0xE, 0x0) => CALL_FETCH + 3078, // Clears the 256 byte display buffer a byte at a time
+        (0x0, 0x0, 0xE, 0xE) => CALL_FETCH + 10,
+        _ => FETCH + match nibbles(opcode) {
+            (0x1, _, _, _) => 12,
+            (0x2, _, _, _) => 26,
+            (0x3, _, _, _) => 10 + skip(vx == nn),
+            (0x4, _, _, _) => 10 + skip(vx != nn),
+            (0x5, _, _, 0x0) => 14 + skip(vx == vy),
+            (0x9, _, _, 0x0) => 14 + skip(vx != vy),
+            (0x6, _, _, _) => 6,
+            (0x7, _, _, _) => 10,
+            (0x8, _, _, 0x0) => 12,
+            (0x8, _, _, _) => 44, // Builds and runs a small 1802 routine for the operation
+            (0xA, _, _, _) => 12,
+            (0xB, _, _, _) => 22,
+            (0xC, _, _, _) => 36,
+            (0xD, _, _, _) => draw_cycles(processor, vx, vy, n),
+            (0xE, _, 0x9, 0xE) => 14 + skip(key),
+            (0xE, _, 0xA, 0x1) => 14 + skip(!key),
+            (0xF, _, 0x0, 0x7) | (0xF, _, 0x1, 0x5) | (0xF, _, 0x1, 0x8) => 10,
+            (0xF, _, 0x0, 0xA) => 22, // Each time it checks the keypad
+            (0xF, _, 0x1, 0xE) => 16 + if (processor.i() & 0xFF) + vx as usize > 0xFF { 6 } else { 0 },
+            (0xF, _, 0x2, 0x9) => 20,
+            (0xF, _, 0x3, 0x3) => 84 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10) as u32, // Counts out each digit
+            (0xF, _, 0x5, 0x5) | (0xF, _, 0x6, 0x5) => 18 + 14 * (x as u32 + 1),
+            _ => 10,
+        },
+    }
+}
+
+// Each row of the sprite is shifted into place a bit at a time. After waiting for the display
+// interrupt, the rows are XORed into two display bytes each, or one in the last column, stopping
+// at the bottom of the screen. A display byte that already has pixels under the sprite costs a
+// little more, since it sets VF. DXY0 draws nothing on the VIP (see `Quirks::large_sprites`), but
+// still waits.
+fn draw_cycles(processor: &Processor, x: u8, y: u8, rows: u16) -> u32 {
+    let (x, y, rows) = (x as usize % 64, y as usize % 32, rows as usize);
+    let (column, shift) = (x / 8, x % 8);
+    let screen = processor.vram();
+    let width = processor.width();
+    let lit = |row: usize, column: usize| (0..8).fold(0u8, |byte, bit| {
+        byte << 1 | screen.get(row * width + column * 8 + bit).is_some_and(|pixel| *pixel != 0) as u8
+    });
+
+    let mut cycles = 66 + rows as u32 * (46 + 20 * shift as u32) + 12;
+    for row in 0..rows.min(32 - y) {
+        let sprite = processor.ram().get(processor.i() + row).copied().unwrap_or(0);
+
+        cycles += 34;
+        if lit(y + row, column) & sprite >> shift != 0 {
+            cycles += 4;
+        }
+        if column < 7 {
+            cycles += 16;
+            if lit(y + row, column + 1) & ((sprite as u16) << (8 - shift)) as u8 != 0 {
+                cycles += 4;
+            }
+        }
+    }
+    if y + rows < 32 {
+        cycles += 4; // Checks for another row instead of running off the bottom
+    }
+
+    cycles + 12
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A VIP processor about to run `opcode`, with the given registers, I pointing at `sprite` and
+    // every pixel set to `lit`
+    fn processor_at(opcode: u16, v: &[(usize, u8)], sprite: &[u8], lit: bool) -> Processor {
+        let mut processor = Processor::new(crate::Quirks::COSMAC_VIP);
+        let mut snapshot = processor.snapshot();
+
+        snapshot.ram[0x200..0x202].copy_from_slice(&opcode.to_be_bytes());
+        snapshot.ram[0x300..0x300 + sprite.len()].copy_from_slice(sprite);
+        snapshot.i = 0x300;
+        for (x, value) in v {
+            snapshot.v[*x] = *value;
+        }
+        snapshot.vram.fill(lit as u8);
+        processor.restore(&snapshot);
+        processor
+    }
+
+    fn cycles(opcode: u16, v: &[(usize, u8)]) -> u32 {
+        vip_cycles(&processor_at(opcode, v, &[], false))
+    }
+
+    #[test]
+    fn costs_match_the_interpreter() {
+        assert_eq!(cycles(0x00E0, &[]), 3118);
+        assert_eq!(cycles(0x00EE, &[]), 50);
+        assert_eq!(cycles(0x6012, &[]), 74);
+        assert_eq!(cycles(0xF033, &[(0, 255)]), 344);
+    }
+
+    #[test]
+    fn taken_skips_cost_more() {
+        assert_eq!(cycles(0x3005, &[(0, 5)]), 82);
+        assert_eq!(cycles(0x3005, &[(0, 6)]), 78);
+        assert_eq!(cycles(0x9010, &[(0, 5), (1, 6)]), 86);
+        assert_eq!(cycles(0x9010, &[(0, 5), (1, 5)]), 82);
+    }
+
+    #[test]
+    fn register_copies_cost_more_per_register() {
+        assert_eq!(cycles(0xFF55, &[]), cycles(0xF055, &[]) + 15 * 14);
+    }
+
+    #[test]
+    fn sprites_cost_more_unaligned_taller_and_over_lit_pixels() {
+        let draw = |x: u8, y: u8, n: u16, sprite: &[u8], lit: bool| {
+            vip_cycles(&processor_at(0xD010 | n, &[(0, x), (1, y)], sprite, lit))
+        };
+
+        assert_eq!(draw(8, 0, 5, &[0xF0; 5], false), 642);
+        assert_eq!(draw(9, 0, 5, &[0xF0; 5], false), 742);
+        // Rows below the bottom edge are shifted but not drawn
+        assert_eq!(draw(8, 30, 5, &[0xF0; 5], false), 488);
+        // Setting VF costs a little, and the last column only has one byte to draw
+        assert_eq!(draw(0, 0, 1, &[0xFF], false), 258);
+        assert_eq!(draw(0, 0, 1, &[0xFF], true), 262);
+        assert_eq!(draw(60, 0, 1, &[0xFF], false), 322);
+        assert_eq!(draw(60, 0, 1, &[0xFF], true), 326);
+    }
+
+    #[test]
+    fn dxy0_draws_nothing_on_the_vip() {
+        assert_eq!(vip_cycles(&processor_at(0xD010, &[(0, 8)], &[0xFF; 32], false)), 162);
+    }
+}
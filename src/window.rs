@@ -0,0 +1,537 @@
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use chip8::audio::{AudioBackend, Beeper, NullBackend, WavBackend};
+use chip8::database;
+use chip8::debugger::{self, Debugger};
+use chip8::frame::{self, FRAME_DURATION, FrameClock, Meter, Speed};
+use chip8::movie::{Movie, Player, Recorder};
+use chip8::palette::Palette;
+use chip8::processor::{MAX_HEIGHT, MAX_WIDTH, Processor, State};
+use chip8::rewind::Rewind;
+use chip8::rng::Rng;
+use chip8::snapshot;
+use chip8::symbols::Symbols;
+
+use pixels::{Error, Pixels, SurfaceTexture};
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, Event, Modifiers, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::{Fullscreen, WindowBuilder};
+
+use crate::cli::{self, FastForward, Failure, Options};
+use crate::keypad::Keymap;
+use crate::rom_config::RomConfig;
+
+/// Runs a rom in a window until it's closed or the program exits.
+pub fn run(options: Options) -> Result<(), Failure> {
+    println!("[+] Initializing emulator...");
+
+    let rom_config = match RomConfig::load(&options.rom) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("[-] Could not load rom config: {}", err);
+            return Err(Failure::Usage);
+        }
+    };
+
+    let database = match cli::load_database(options.database.as_deref()) {
+        Ok(database) => database,
+        Err(err) => {
+            eprintln!("[-] Could not load rom database: {}", err);
+            return Err(Failure::Usage);
+        }
+    };
+
+    let mut beeper = Beeper::new(open_audio_backend(&options));
+
+    env_logger::init(); // WGPU will fail silently without this 
+    
+    let event_loop = EventLoop::new().unwrap();
+
+    let window = {
+        WindowBuilder::new()
+            .with_title("CHIP8")
+            .with_inner_size(LogicalSize::new(64 * options.scale, 32 * options.scale))
+            .with_min_inner_size(LogicalSize::new(64, 32))
+            .with_fullscreen(options.fullscreen.then_some(Fullscreen::Borderless(None)))
+            .build(&event_loop)
+            .unwrap()
+    };
+
+    let mut pixels = {
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        Pixels::new(64, 32, surface_texture).unwrap()
+    };
+
+    // A replay has to run with the settings it was recorded with
+    let replay = match &options.replay {
+        Some(path) => match Movie::load(path) {
+            Ok(movie) => Some(movie),
+            Err(err) => {
+                eprintln!("[-] Could not load movie: {}", err);
+                return Err(Failure::Error);
+            }
+        },
+        None => None,
+    };
+
+    // The rom is read first, since its hash picks the settings the database recommends for it
+    println!("[+] Loading rom...");
+    let rom = cli::read_rom(&options.rom).map_err(|err| {
+        eprintln!("[-] Could not read {}: {}", options.rom, err);
+        Failure::Load
+    })?;
+
+    let rom_info = database.get(&database::hash(&rom));
+    if let Some(info) = rom_info {
+        println!("[+] Found {} in the rom database, using its recommended settings", info.title);
+    }
+
+    let (quirks, random, seed, cpu_speed, origin) = match &replay {
+        Some(movie) => (movie.quirks, movie.random, movie.seed, movie.speed, movie.origin),
+        None => (
+            options.platform.or(rom_config.quirks).or(rom_info.and_then(|info| info.quirks)).unwrap_or(options.defaults.quirks),
+            options.random,
+            options.seed.unwrap_or_else(rand::random),
+            options.speed.or(rom_config.speed).or(rom_info.and_then(|info| info.speed)).unwrap_or(options.defaults.speed),
+            options.origin
+        ),
+    };
+    let mut speed = Speed::new(cpu_speed);
+
+    let mut processor = Processor::new(quirks);
+    match processor.load_bytes(&rom, origin) {
+        Ok(size) => println!("[+] Loaded {} bytes at {:#05x}", size, origin),
+        Err(err) => {
+            eprintln!("[-] Could not load {}: {}", options.rom, err);
+            return Err(Failure::Load);
+        }
+    }
+
+    println!("[+] Random seed: {} (pass --seed {} to repeat this run)", seed, seed);
+    processor.set_rng(Rng::new(random, seed));
+
+    let mut recorder = options.record.is_some().then(|| Recorder::new(Movie::new(seed, random, quirks, cpu_speed, origin)));
+    let mut player = replay.map(|movie| {
+        println!("[+] Replaying movie, keyboard input is ignored until it ends");
+        Player::new(movie)
+    });
+
+    let keymap = rom_info.map_or_else(Keymap::default, |info| Keymap::with_layout(&info.keys));
+    let keymap = match keymap.with_bindings(&options.keys) {
+        Ok(keymap) => keymap,
+        Err(err) => {
+            eprintln!("[-] Could not load input.keys from the config: {}", err);
+            return Err(Failure::Usage);
+        }
+    };
+    let keymap = match &options.keymap {
+        Some(path) => match Keymap::load(path, keymap) {
+            Ok(keymap) => keymap,
+            Err(err) => {
+                eprintln!("[-] Could not load key mapping: {}", err);
+                return Err(Failure::Error);
+            }
+        },
+        None => keymap,
+    };
+    let palette = options.palette.or(rom_config.palette).or(rom_info.and_then(|info| info.palette)).unwrap_or(options.defaults.palette);
+    
+    let symbols = match &options.symbols {
+        Some(path) => match Symbols::load(path) {
+            Ok(symbols) => symbols,
+            Err(err) => {
+                eprintln!("[-] Could not load symbols: {}", err);
+                return Err(Failure::Error);
+            }
+        },
+        None => Symbols::default(),
+    };
+
+    let mut debugger = options.debug.then(|| {
+        println!("[+] Debugger enabled, emulation is paused. Type `continue` to start.");
+        println!("{}", debugger::HELP);
+        (Debugger::new(symbols), debugger::spawn_console())
+    });
+
+    println!("[+] Starting emulation cycle...");
+
+    let mut clock = FrameClock::new();
+    let mut meter = Meter::new();
+    
+    let mut latest_state = State { vram: [0; MAX_WIDTH * MAX_HEIGHT], width: 64, height: 32, vram_updated: false };
+    let mut buffer_size = (64, 32);
+    let mut redraw_pending = false; // Set when the screen changes outside the emulation cycle
+    let mut modifiers = Modifiers::default();
+    let mut rewind = Rewind::new(options.rewind_seconds * 60); // One snapshot per frame
+    let mut rewinding = false;
+    let mut pace = Pace::Normal;
+    let mut paused = false;
+    let mut advance = false; // Set to run a single frame while paused
+
+    let mut failure = None; // Set when the emulator shuts down because something went wrong
+    let res = event_loop.run(|event, elwt| {
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => {
+                println!("[+] Shutting down emulator...");
+                elwt.exit();
+            }
+            Event::WindowEvent {
+                event: WindowEvent::RedrawRequested,
+                ..
+            } => {
+                draw(pixels.frame_mut(), &latest_state, &palette);
+                
+                if let Err(err) = pixels.render() {
+                    eprintln!("Render error: {}", err);
+                    failure = Some(Failure::Error);
+                    elwt.exit();
+                }
+            },
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(new_modifiers),
+                ..
+            } => modifiers = new_modifiers,
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput { event, .. },
+                ..
+            } => {
+                let PhysicalKey::Code(code) = event.physical_key else { return };
+
+                // Jumping to another point in time would make the movie impossible to replay
+                let movie_active = recorder.is_some() || player.is_some();
+                let pressed = event.state == ElementState::Pressed;
+
+                if matches!(code, KeyCode::Minus | KeyCode::Equal) {
+                    if pressed && movie_active {
+                        if !event.repeat {
+                            println!("[-] Changing the speed is disabled while a movie is recording or playing");
+                        }
+                    } else if pressed {
+                        if code == KeyCode::Equal {
+                            speed.faster();
+                        } else {
+                            speed.slower();
+                        }
+                        println!("[+] Speed: {}", speed);
+                    }
+                } else if code == KeyCode::F10 {
+                    if pressed && !event.repeat {
+                        paused = !paused;
+                        if paused {
+                            beeper.update(0, None);
+                        }
+                        window.set_title(&title(&meter, pace, paused, options.fast_forward));
+                    }
+                } else if code == KeyCode::F11 { // Frame advance, hold to keep stepping
+                    advance |= pressed && paused;
+                } else if let Some(new_pace) = Pace::from_key(code) {
+                    if pressed && !event.repeat {
+                        pace = if pace == new_pace { Pace::Normal } else { new_pace };
+                        if let Some(rate) = pace.rate(options.fast_forward) {
+                            clock.set_rate(rate);
+                        }
+                        window.set_title(&title(&meter, pace, paused, options.fast_forward));
+                    }
+                } else if code == KeyCode::Backquote { // Hold to rewind
+                    rewinding = pressed && !movie_active;
+                    if pressed && !event.repeat && movie_active {
+                        println!("[-] Rewinding is disabled while a movie is recording or playing");
+                    }
+                } else if let Some(slot) = save_slot(code) {
+                    if pressed && !event.repeat {
+                        // Shift+F1-F9 saves to a slot, F1-F9 loads it
+                        if modifiers.state().shift_key() {
+                            save_state(&processor, &options, slot);
+                        } else if movie_active {
+                            println!("[-] Loading states is disabled while a movie is recording or playing");
+                        } else if load_state(&mut processor, &options, slot) {
+                            rewind.clear(); // The history leads up to a different state
+                            latest_state = processor.state();
+                            redraw_pending = true;
+                        }
+                    }
+                } else if let Some(key) = keymap.get(code)
+                    && player.is_none()
+                {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.key(key, pressed);
+                    }
+                    processor.set_key(key, pressed);
+                }
+            },
+            Event::AboutToWait => {
+                let mut vram_updated = std::mem::take(&mut redraw_pending);
+
+                if let Some((debugger, console)) = &mut debugger {
+                    for line in console.try_iter() {
+                        let output = debugger.execute(&line, &mut processor);
+                        if !output.is_empty() {
+                            println!("{}", output);
+                        }
+
+                        latest_state = processor.state();
+                        vram_updated = true;
+                    }
+                }
+
+                let debugger_paused = debugger.as_ref().is_some_and(|(debugger, _)| !debugger.is_running());
+                let rate = pace.rate(options.fast_forward);
+                let frames = if paused || debugger_paused {
+                    clock.resync(); // Don't try to catch up on the time spent paused
+                    u32::from(std::mem::take(&mut advance) && !debugger_paused)
+                } else if rate.is_none() {
+                    clock.resync();
+                    u32::MAX // Uncapped fast-forward runs until the deadline below
+                } else {
+                    clock.frames_due()
+                };
+
+                // Hand control back to the window at least once a frame, so it keeps drawing and
+                // reading keys however fast the emulator runs
+                let deadline = Instant::now() + FRAME_DURATION;
+                for _ in 0..frames {
+                    if rate.is_none() && Instant::now() >= deadline {
+                        break;
+                    }
+
+                    if rewinding {
+                        beeper.update(0, None);
+
+                        if let Some(snapshot) = rewind.step_back(options.rewind_speed) {
+                            processor.restore(snapshot);
+                            latest_state = processor.state();
+                            vram_updated = true;
+                        }
+                        continue;
+                    }
+
+                    if let Some(player) = &mut player {
+                        player.start_frame(&mut processor);
+                    }
+
+                    beeper.update(processor.sound_timer, processor.audio_pattern());
+
+                    match frame::run_frame(&mut processor, debugger.as_mut().map(|(debugger, _)| debugger), &mut speed) {
+                        Ok(result) => {
+                            vram_updated |= result.vram_updated;
+                            meter.record(&result);
+                        },
+                        Err(_) if debugger.is_some() => {}, // The debugger pauses and reports the fault
+                        Err(err) => {
+                            eprintln!("[-] Emulation fault: {}", err);
+                            failure = Some(Failure::Fault);
+                            elwt.exit();
+                            return;
+                        }
+                    }
+                    latest_state = processor.state();
+
+                    rewind.record(processor.snapshot());
+
+                    if let Some(recorder) = &mut recorder {
+                        recorder.end_frame(&processor);
+                    }
+                    if let Some(replay) = &mut player {
+                        if let Some(frame) = replay.end_frame(&processor) {
+                            println!("[-] Replay diverged from the recording at frame {}", frame);
+                        }
+                        if replay.finished() {
+                            println!("[+] Replay finished, keyboard input is live again");
+                            player = None;
+                        }
+                    }
+
+                    if processor.halted() {
+                        println!("[+] Program exited, shutting down emulator...");
+                        elwt.exit();
+                        return;
+                    }
+
+                    // A breakpoint or watchpoint stops the rest of the frames too
+                    if debugger.as_ref().is_some_and(|(debugger, _)| !debugger.is_running()) {
+                        break;
+                    }
+                }
+
+                if meter.update() {
+                    window.set_title(&title(&meter, pace, paused, options.fast_forward));
+                }
+
+                if let Some((debugger, _)) = &mut debugger
+                    && let Some(reason) = debugger.take_stop_reason()
+                {
+                    println!("{}", debugger.describe(&reason, &processor));
+                }
+
+                if vram_updated {
+                    // The program may have switched between lo-res and hi-res mode
+                    let size = (latest_state.width as u32, latest_state.height as u32);
+                    if size != buffer_size {
+                        if let Err(err) = pixels.resize_buffer(size.0, size.1) {
+                            eprintln!("Resize error: {}", err);
+                            failure = Some(Failure::Error);
+                            elwt.exit();
+                        }
+                        buffer_size = size;
+                    }
+
+                    window.request_redraw();
+                }
+
+                // Sleep until the next frame is due. While paused this keeps checking the debugger console.
+                if rate.is_none() && !paused && !debugger_paused {
+                    elwt.set_control_flow(ControlFlow::Poll);
+                } else {
+                    elwt.set_control_flow(ControlFlow::WaitUntil(clock.next_frame()));
+                }
+            },
+            _ => {},
+        }
+    });
+    let _ = res.map_err(|e| Error::UserDefined(Box::new(e)));
+
+    if let (Some(recorder), Some(path)) = (&recorder, &options.record) {
+        match recorder.movie().save(path) {
+            Ok(()) => println!("[+] Saved movie to {}", path),
+            Err(err) => {
+                eprintln!("[-] Could not save movie: {}", err);
+                failure = failure.or(Some(Failure::Error));
+            }
+        }
+    }
+
+    failure.map_or(Ok(()), Err)
+}
+
+/// How fast emulation runs compared to real time.
+#[derive(Clone, Copy, PartialEq)]
+enum Pace {
+    Normal,
+    FastForward,
+    SlowMotion
+}
+
+const SLOW_MOTION_RATE: f64 = 0.25;
+
+impl Pace {
+    fn from_key(code: KeyCode) -> Option<Self> {
+        match code {
+            KeyCode::BracketRight => Some(Pace::FastForward),
+            KeyCode::BracketLeft => Some(Pace::SlowMotion),
+            _ => None,
+        }
+    }
+
+    // None when fast-forward is uncapped
+    fn rate(self, fast_forward: FastForward) -> Option<f64> {
+        match (self, fast_forward) {
+            (Pace::Normal, _) => Some(1.0),
+            (Pace::SlowMotion, _) => Some(SLOW_MOTION_RATE),
+            (Pace::FastForward, FastForward::Rate(rate)) => Some(rate),
+            (Pace::FastForward, FastForward::Uncapped) => None,
+        }
+    }
+}
+
+fn title(meter: &Meter, pace: Pace, paused: bool, fast_forward: FastForward) -> String {
+    let mode = match (paused, pace, fast_forward) {
+        (true, _, _) => " - Paused".to_string(),
+        (false, Pace::Normal, _) => String::new(),
+        (false, Pace::SlowMotion, _) => format!(" - Slow motion x{}", SLOW_MOTION_RATE),
+        (false, Pace::FastForward, FastForward::Rate(rate)) => format!(" - Fast-forward x{}", rate),
+        (false, Pace::FastForward, FastForward::Uncapped) => " - Fast-forward (uncapped)".to_string(),
+    };
+
+    format!("CHIP8 - {:.0} FPS, {:.0} IPS{}", meter.fps(), meter.ips(), mode)
+}
+
+fn save_slot(code: KeyCode) -> Option<u8> {
+    match code {
+        KeyCode::F1 => Some(1),
+        KeyCode::F2 => Some(2),
+        KeyCode::F3 => Some(3),
+        KeyCode::F4 => Some(4),
+        KeyCode::F5 => Some(5),
+        KeyCode::F6 => Some(6),
+        KeyCode::F7 => Some(7),
+        KeyCode::F8 => Some(8),
+        KeyCode::F9 => Some(9),
+        _ => None,
+    }
+}
+
+// Save states go next to the rom, or in the directory set in the config file. A rom read from
+// stdin has no name to give them.
+fn state_path(options: &Options, slot: u8) -> Option<PathBuf> {
+    if options.rom == "-" {
+        println!("[-] Save states need a rom file, not one read from stdin");
+        return None;
+    }
+
+    let path = snapshot::slot_path(&options.rom, slot);
+    match (&options.saves, path.file_name()) {
+        (Some(saves), Some(name)) => Some(Path::new(saves).join(name)),
+        _ => Some(path),
+    }
+}
+
+fn save_state(processor: &Processor, options: &Options, slot: u8) {
+    let Some(path) = state_path(options, slot) else { return };
+    match snapshot::save(&processor.snapshot(), &path) {
+        Ok(()) => println!("[+] Saved state to slot {} ({})", slot, path.display()),
+        Err(err) => eprintln!("[-] Could not save state to {}: {}", path.display(), err),
+    }
+}
+
+// Returns true if the processor was restored
+fn load_state(processor: &mut Processor, options: &Options, slot: u8) -> bool {
+    let Some(path) = state_path(options, slot) else { return false };
+    match snapshot::load(&path) {
+        Ok(snapshot) => {
+            processor.restore(&snapshot);
+            println!("[+] Loaded state from slot {}", slot);
+            true
+        },
+        Err(err) => {
+            eprintln!("[-] Could not load state from {}: {}", path.display(), err);
+            false
+        }
+    }
+}
+
+fn open_audio_backend(options: &Options) -> Box<dyn AudioBackend> {
+    if options.mute {
+        return Box::new(NullBackend::default());
+    }
+
+    if let Some(path) = &options.wav {
+        match WavBackend::create(path, options.tone) {
+            Ok(backend) => return Box::new(backend),
+            Err(err) => eprintln!("[-] Could not create {}: {}", path, err),
+        }
+        return Box::new(NullBackend::default());
+    }
+
+    #[cfg(feature = "cpal")]
+    match chip8::audio::DeviceBackend::open(options.tone) {
+        Ok(backend) => return Box::new(backend),
+        Err(err) => eprintln!("[-] Could not open audio device: {}", err),
+    }
+    #[cfg(not(feature = "cpal"))]
+    println!("[+] Sound is off: this build has no audio device support (build with `--features cpal`, or use --wav)");
+
+    Box::new(NullBackend::default())
+}
+
+fn draw(frame: &mut [u8], state: &State, palette: &Palette) {
+    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+        pixel.copy_from_slice(&palette.color(state.vram[i]));
+    }
+}
@@ -0,0 +1,131 @@
+//! Runs test roms headless and compares the screen they end on with a golden image in
+//! `tests/golden`. Set `CHIP8_BLESS=1` to write the golden images from the current output instead,
+//! after checking by eye that the screen is right.
+//!
+//! The Timendus test suite isn't included in the repository, so those tests are ignored until its
+//! roms are put in `tests/roms` (see the README there).
+
+use std::{env, fs, path::{Path, PathBuf}};
+
+use chip8::asm::{self, Target};
+use chip8::frame::{self, Speed};
+use chip8::movie::Input;
+use chip8::rng::{RandomMode, Rng};
+use chip8::processor::PROGRAM_START;
+use chip8::{Processor, Quirks};
+
+// Most test roms finish well within this
+const MAX_FRAMES: u64 = 600;
+
+fn test_dir(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(name)
+}
+
+// Runs a rom until it's stuck jumping to itself, pressing and releasing keys on the way
+fn run_rom(rom: &[u8], quirks: Quirks, platform: Option<u8>, inputs: &[Input]) -> Processor {
+    let mut processor = Processor::new(quirks);
+    processor.set_rng(Rng::new(RandomMode::Xorshift, 0));
+    processor.load_bytes(rom, PROGRAM_START).unwrap();
+
+    // The Timendus quirks test reads the platform to test from 0x1FF instead of asking for it
+    if let Some(platform) = platform {
+        let mut snapshot = processor.snapshot();
+        snapshot.ram[0x1FF] = platform;
+        processor.restore(&snapshot);
+    }
+
+    let mut speed = Speed::default();
+    let mut inputs = inputs.iter().peekable();
+    for frames in 0..MAX_FRAMES {
+        while let Some(input) = inputs.next_if(|input| input.frame <= frames) {
+            processor.set_key(input.key, input.pressed);
+        }
+
+        frame::run_frame(&mut processor, None, &mut speed).unwrap();
+
+        let pc = processor.pc();
+        let idle = processor.ram()[pc..pc + 2] == (0x1000 | pc as u16).to_be_bytes();
+        if idle && inputs.peek().is_none() {
+            break;
+        }
+    }
+
+    processor
+}
+
+fn assert_screen(processor: &Processor, golden: &str) {
+    let screen = processor.state().ascii_art();
+    let path = test_dir("golden").join(format!("{}.txt", golden));
+
+    if env::var_os("CHIP8_BLESS").is_some() {
+        fs::write(&path, &screen).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Could not read {} ({}), run with CHIP8_BLESS=1 to create it", path.display(), err));
+    assert!(screen == expected, "{} doesn't match {}:\n{}", golden, path.display(), screen);
+}
+
+fn timendus_rom(name: &str) -> Vec<u8> {
+    let path = test_dir("roms").join(name);
+    fs::read(&path).unwrap_or_else(|err| panic!("Could not read {} ({}), see tests/roms/README.md", path.display(), err))
+}
+
+#[test]
+fn smoke() {
+    let assembly = asm::assemble_file(&test_dir("roms").join("smoke.s"), Target::Chip8).unwrap();
+
+    let processor = run_rom(&assembly.rom, Quirks::COSMAC_VIP, None, &[]);
+    assert_screen(&processor, "smoke");
+}
+
+#[test]
+#[ignore = "needs the Timendus test roms in tests/roms"]
+fn corax_plus() {
+    let processor = run_rom(&timendus_rom("3-corax+.ch8"), Quirks::COSMAC_VIP, None, &[]);
+    assert_screen(&processor, "corax+");
+}
+
+#[test]
+#[ignore = "needs the Timendus test roms in tests/roms"]
+fn flags() {
+    let processor = run_rom(&timendus_rom("4-flags.ch8"), Quirks::COSMAC_VIP, None, &[]);
+    assert_screen(&processor, "flags");
+}
+
+#[test]
+#[ignore = "needs the Timendus test roms in tests/roms"]
+fn quirks_vip() {
+    let processor = run_rom(&timendus_rom("5-quirks.ch8"), Quirks::COSMAC_VIP, Some(1), &[]);
+    assert_screen(&processor, "quirks-vip");
+}
+
+#[test]
+#[ignore = "needs the Timendus test roms in tests/roms"]
+fn quirks_schip() {
+    let processor = run_rom(&timendus_rom("5-quirks.ch8"), Quirks::SCHIP, Some(2), &[]);
+    assert_screen(&processor, "quirks-schip");
+}
+
+#[test]
+#[ignore = "needs the Timendus test roms in tests/roms"]
+fn quirks_xochip() {
+    let processor = run_rom(&timendus_rom("5-quirks.ch8"), Quirks::XO_CHIP, Some(3), &[]);
+    assert_screen(&processor, "quirks-xochip");
+}
+
+#[test]
+#[ignore = "needs the Timendus test roms in tests/roms"]
+fn keypad() {
+    // Picks the FX0A test from the menu with key 3, then presses and releases A
+    let inputs = [
+        Input { frame: 10, key: 0x3, pressed: true },
+        Input { frame: 15, key: 0x3, pressed: false },
+        Input { frame: 60, key: 0xA, pressed: true },
+        Input { frame: 70, key: 0xA, pressed: false }
+    ];
+
+    let processor = run_rom(&timendus_rom("6-keypad.ch8"), Quirks::COSMAC_VIP, None, &inputs);
+    assert_screen(&processor, "keypad");
+}
@@ -0,0 +1,49 @@
+; Draws the result of a few calculations with the built-in font, and a sprite from data, then
+; stops in a loop. Used by the conformance tests to check the harness itself.
+
+X equ 2
+
+        cls
+        ld v0, 200          ; 200 + 56 = 256, so v0 wraps to 0 and vf is set
+        ld v1, 56
+        add v0, v1
+        ld v5, vf
+        ld v2, 123
+        call number
+        ld v0, X
+        ld v1, 10
+        ld f, v5            ; The carry flag, 1
+        drw v0, v1, 5
+        ld v0, 20
+        ld i, box
+        drw v0, v1, 8
+        drw v0, v1, 8       ; Erasing it again sets the collision flag
+        ld v6, vf
+        drw v0, v1, 8
+        ld v0, 30
+        ld f, v6            ; The collision flag, 1
+        drw v0, v1, 5
+end:
+        jp end
+
+; Draws the decimal digits of v2 in the top left corner
+number:
+        ld i, digits
+        ld b, v2
+        ld v2, [i]
+        ld v3, X
+        ld v4, 2
+        ld f, v0
+        drw v3, v4, 5
+        add v3, 5
+        ld f, v1
+        drw v3, v4, 5
+        add v3, 5
+        ld f, v2
+        drw v3, v4, 5
+        ret
+
+box:
+        db 0xFF, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xFF
+digits:
+        db 0, 0, 0